@@ -0,0 +1,167 @@
+use crate::engine::{self, SearchResult};
+use crate::eval::Weights;
+use crate::state::{ChessState, Color, Move};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+// How deep the analysis goes unless a new position comes in first
+const MAX_DEPTH: u32 = 32;
+
+// The engine's opinion on a position
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Evaluation {
+    // the position that was searched
+    pub state: ChessState,
+    pub depth: u32,
+    // in centipawns from White's point of view
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+impl Evaluation {
+    fn new(state: &ChessState, result: &SearchResult) -> Self {
+        let score = match state.turn {
+            Color::White => result.score,
+            Color::Black => -result.score,
+        };
+        Evaluation {state: state.clone(), depth: result.depth, score, pv: result.pv.clone()}
+    }
+}
+
+// Formats a score from White's point of view in pawns, e.g. "+0.35", or as
+// the number of moves to a mate, e.g. "#3" or "#-2"
+pub fn format_score(score: i32) -> String {
+    if engine::is_mate_score(score) {
+        let moves = (engine::MATE - score.abs() + 1) / 2;
+        format!("#{}", score.signum() * moves)
+    } else {
+        format!("{:+.2}", score as f64 / 100.0)
+    }
+}
+
+// The share of White in the evaluation bar, between 0 and 1
+pub fn white_share(score: i32) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-score as f64 / 400.0))
+}
+
+// Searches positions in a background thread, deeper and deeper until a new
+// position is asked for
+pub struct Analysis {
+    positions: Sender<(u64, ChessState, usize, Arc<Weights>)>,
+    evaluations: Receiver<(u64, Evaluation)>,
+    // counts the positions asked for, the search stops when it changes
+    generation: Arc<AtomicU64>,
+    position: Option<ChessState>,
+    evaluation: Option<Evaluation>,
+    // searching with
+    threads: usize,
+    weights: Arc<Weights>,
+}
+
+impl Default for Analysis {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Analysis {
+    pub fn new() -> Self {
+        let (positions, position_receiver) = mpsc::channel::<(u64, ChessState, usize, Arc<Weights>)>();
+        let (evaluation_sender, evaluations) = mpsc::channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let current = generation.clone();
+        thread::spawn(move || {
+            while let Ok(mut position) = position_receiver.recv() {
+                // only the latest position is of interest
+                while let Ok(newer) = position_receiver.try_recv() {
+                    position = newer;
+                }
+                let (id, mut state, threads, weights) = position;
+                let stop = || current.load(Ordering::Relaxed) != id;
+                let searched = state.clone();
+                engine::search_threads(&mut state, MAX_DEPTH, threads, weights, &stop, |result| {
+                    let _ = evaluation_sender.send((id, Evaluation::new(&searched, result)));
+                });
+            }
+        });
+        Analysis {positions, evaluations, generation, position: None, evaluation: None, threads: 1,
+                  weights: Arc::new(Weights::default())}
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn weights(mut self, weights: Arc<Weights>) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    // Starts analysing the position, unless it is already being analysed
+    pub fn analyze(&mut self, state: &ChessState) {
+        if self.position.as_ref() == Some(state) {
+            return;
+        }
+        let id = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        self.position = Some(state.clone());
+        self.evaluation = None;
+        let _ = self.positions.send((id, state.clone(), self.threads, self.weights.clone()));
+    }
+
+    // The deepest evaluation of the position so far
+    pub fn evaluation(&mut self) -> Option<&Evaluation> {
+        let current = self.generation.load(Ordering::Relaxed);
+        while let Ok((id, evaluation)) = self.evaluations.try_recv() {
+            if id == current {
+                self.evaluation = Some(evaluation);
+            }
+        }
+        self.evaluation.as_ref()
+    }
+}
+
+impl Drop for Analysis {
+    fn drop(&mut self) {
+        // stops the search, the thread ends with the channel
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_for_mate(analysis: &mut Analysis) -> Evaluation {
+        let started = Instant::now();
+        loop {
+            if let Some(evaluation) = analysis.evaluation() {
+                if engine::is_mate_score(evaluation.score) {
+                    return evaluation.clone();
+                }
+            }
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn follows_the_position() {
+        let mut analysis = Analysis::new().threads(2);
+        analysis.analyze(&ChessState::new());
+        let state = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 b - - 0 1").unwrap();
+        analysis.analyze(&state);
+        let mate = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        analysis.analyze(&mate);
+        let evaluation = wait_for_mate(&mut analysis);
+        assert_eq!(evaluation.state, mate);
+        assert_eq!(evaluation.pv[0].to_string(), "a1a8");
+        assert_eq!(format_score(evaluation.score), "#1");
+        assert_eq!(format_score(-engine::MATE + 4), "#-2");
+        assert_eq!(format_score(-35), "-0.35");
+        assert!(white_share(0) == 0.5 && white_share(400) > 0.9);
+    }
+}
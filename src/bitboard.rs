@@ -0,0 +1,273 @@
+use crate::state::{Color, Pos};
+use std::sync::OnceLock;
+
+// A set of squares with one bit per square. Bit 0 is a1, bit 7 is h1 and
+// bit 63 is h8.
+pub type Bitboard = u64;
+
+pub fn square(pos: Pos) -> usize {
+    (pos.rank - 1) * 8 + pos.file - 1
+}
+
+pub fn pos(square: usize) -> Pos {
+    Pos::new(square % 8 + 1, square / 8 + 1).unwrap()
+}
+
+pub fn bit(square: usize) -> Bitboard {
+    1 << square
+}
+
+// Iterates over the squares of a bitboard, lowest first
+pub struct Squares(pub Bitboard);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None;
+        }
+        let square = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+        Some(square)
+    }
+}
+
+// Fancy magic bitboards: the relevant blockers of a sliding piece are
+// multiplied by a magic number, the top bits of the product index into the
+// shared attack table.
+struct Magic {
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupied: Bitboard) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+struct Tables {
+    knight: [Bitboard; 64],
+    king: [Bitboard; 64],
+    pawn: [[Bitboard; 64]; 2],
+    rook: Vec<Magic>,
+    bishop: Vec<Magic>,
+    attacks: Vec<Bitboard>,
+    between: Vec<[Bitboard; 64]>,
+    line: Vec<[Bitboard; 64]>,
+}
+
+const ROOK_DIRS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+fn offset(square: usize, (file_delta, rank_delta): (i32, i32)) -> Option<usize> {
+    let file = (square % 8) as i32 + file_delta;
+    let rank = (square / 8) as i32 + rank_delta;
+    if (0..8).contains(&file) && (0..8).contains(&rank) {
+        Some((rank * 8 + file) as usize)
+    } else {
+        None
+    }
+}
+
+fn step_attacks(square: usize, deltas: &[(i32, i32)]) -> Bitboard {
+    deltas.iter()
+        .filter_map(|&delta| offset(square, delta))
+        .fold(0, |attacks, to| attacks | bit(to))
+}
+
+// Walks the rays one square at a time, only used to fill the tables
+fn slider_attacks(square: usize, occupied: Bitboard, dirs: &[(i32, i32)]) -> Bitboard {
+    let mut attacks = 0;
+    for &dir in dirs {
+        let mut cur = square;
+        while let Some(next) = offset(cur, dir) {
+            attacks |= bit(next);
+            if occupied & bit(next) != 0 {
+                break;
+            }
+            cur = next;
+        }
+    }
+    attacks
+}
+
+// The squares whose occupancy matters for a slider, which excludes the last
+// square of every ray
+fn relevant_mask(square: usize, dirs: &[(i32, i32)]) -> Bitboard {
+    let mut mask = 0;
+    for &dir in dirs {
+        let mut cur = square;
+        while let Some(next) = offset(cur, dir) {
+            if offset(next, dir).is_none() {
+                break;
+            }
+            mask |= bit(next);
+            cur = next;
+        }
+    }
+    mask
+}
+
+// xorshift64*, seeded with constants so that the tables are the same on
+// every run
+struct Rng(u64);
+
+// Seeds per rank known to find magics quickly, taken from Stockfish
+const SEEDS: [u64; 8] = [728, 10316, 55013, 32803, 12281, 15100, 16645, 255];
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn sparse(&mut self) -> u64 {
+        self.next() & self.next() & self.next()
+    }
+}
+
+fn find_magics(dirs: &[(i32, i32)], attacks: &mut Vec<Bitboard>) -> Vec<Magic> {
+    let mut magics = Vec::with_capacity(64);
+    for square in 0..64 {
+        let mut rng = Rng(SEEDS[square / 8]);
+        let mask = relevant_mask(square, dirs);
+        let bits = mask.count_ones();
+        let size = 1 << bits;
+        // enumerate all subsets of the mask with the carry-rippler trick
+        let mut occupancies = Vec::with_capacity(size);
+        let mut reference = Vec::with_capacity(size);
+        let mut subset: Bitboard = 0;
+        loop {
+            occupancies.push(subset);
+            reference.push(slider_attacks(square, subset, dirs));
+            subset = subset.wrapping_sub(mask) & mask;
+            if subset == 0 {
+                break;
+            }
+        }
+        let offset = attacks.len();
+        attacks.resize(offset + size, 0);
+        let mut epoch = vec![0u32; size];
+        let mut tries = 0;
+        let magic = loop {
+            let candidate = rng.sparse();
+            if (mask.wrapping_mul(candidate) >> 56).count_ones() < 6 {
+                continue;
+            }
+            tries += 1;
+            let magic = Magic {mask, magic: candidate, shift: 64 - bits, offset};
+            let mut ok = true;
+            for (occupied, &reference) in occupancies.iter().zip(&reference) {
+                let index = magic.index(*occupied) - offset;
+                if epoch[index] < tries {
+                    epoch[index] = tries;
+                    attacks[offset + index] = reference;
+                } else if attacks[offset + index] != reference {
+                    ok = false;
+                    break;
+                }
+            }
+            if ok {
+                break magic;
+            }
+        };
+        magics.push(magic);
+    }
+    magics
+}
+
+fn init() -> Tables {
+    let knight_deltas = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+    let king_deltas = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+    let mut knight = [0; 64];
+    let mut king = [0; 64];
+    let mut pawn = [[0; 64]; 2];
+    for square in 0..64 {
+        knight[square] = step_attacks(square, &knight_deltas);
+        king[square] = step_attacks(square, &king_deltas);
+        pawn[Color::White.index()][square] = step_attacks(square, &[(-1, 1), (1, 1)]);
+        pawn[Color::Black.index()][square] = step_attacks(square, &[(-1, -1), (1, -1)]);
+    }
+
+    let mut attacks = vec![];
+    let rook = find_magics(&ROOK_DIRS, &mut attacks);
+    let bishop = find_magics(&BISHOP_DIRS, &mut attacks);
+
+    let mut between = vec![[0; 64]; 64];
+    let mut line = vec![[0; 64]; 64];
+    for from in 0..64 {
+        for &dir in ROOK_DIRS.iter().chain(BISHOP_DIRS.iter()) {
+            let mut full = bit(from);
+            let mut cur = from;
+            while let Some(next) = offset(cur, dir) {
+                full |= bit(next);
+                cur = next;
+            }
+            cur = from;
+            while let Some(next) = offset(cur, (-dir.0, -dir.1)) {
+                full |= bit(next);
+                cur = next;
+            }
+            let mut passed = 0;
+            cur = from;
+            while let Some(next) = offset(cur, dir) {
+                between[from][next] = passed;
+                line[from][next] = full;
+                passed |= bit(next);
+                cur = next;
+            }
+        }
+    }
+
+    Tables {knight, king, pawn, rook, bishop, attacks, between, line}
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(init)
+}
+
+pub fn knight_attacks(square: usize) -> Bitboard {
+    tables().knight[square]
+}
+
+pub fn king_attacks(square: usize) -> Bitboard {
+    tables().king[square]
+}
+
+// The squares a Pawn of the given color on the square attacks
+pub fn pawn_attacks(color: Color, square: usize) -> Bitboard {
+    tables().pawn[color.index()][square]
+}
+
+pub fn rook_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    let tables = tables();
+    tables.attacks[tables.rook[square].index(occupied)]
+}
+
+pub fn bishop_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    let tables = tables();
+    tables.attacks[tables.bishop[square].index(occupied)]
+}
+
+pub fn queen_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    rook_attacks(square, occupied) | bishop_attacks(square, occupied)
+}
+
+// The squares strictly between two squares on a line, empty if the squares
+// are not on a common rank, file or diagonal
+pub fn between(a: usize, b: usize) -> Bitboard {
+    tables().between[a][b]
+}
+
+// The whole rank, file or diagonal through both squares, empty if there is
+// none
+pub fn line(a: usize, b: usize) -> Bitboard {
+    tables().line[a][b]
+}
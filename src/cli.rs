@@ -0,0 +1,242 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use tress::{ColorDepth, PieceSet, TimeControl};
+
+#[derive(Parser, Debug)]
+#[command(name = "tress", version, about = "Chess in the terminal")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    // without a subcommand a game is played
+    #[command(flatten)]
+    pub play: PlayArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Play a game in the terminal (the default)
+    Play(PlayArgs),
+    /// Search a position and print the evaluation and best line per depth
+    Analyze {
+        #[command(flatten)]
+        position: PositionArgs,
+        /// Depth to search to, in half moves
+        #[arg(long, default_value_t = 6)]
+        depth: u32,
+        /// Threads to search with [default: the number of CPU cores]
+        #[arg(long)]
+        threads: Option<usize>,
+        /// Evaluation weights to search with, as written by tune [default: the built-in ones]
+        #[arg(long, value_name = "FILE")]
+        weights: Option<PathBuf>,
+    },
+    /// Show each term of the static evaluation of a position for both sides
+    Eval {
+        #[command(flatten)]
+        position: PositionArgs,
+        /// Evaluation weights to use, as written by tune [default: the built-in ones]
+        #[arg(long, value_name = "FILE")]
+        weights: Option<PathBuf>,
+    },
+    /// Tune the evaluation weights to the results of a dataset of positions (Texel method)
+    Tune {
+        /// Positions with the result of their game, one per line as a FEN followed by [1.0], [0.5], [0.0], 1-0, 1/2-1/2 or 0-1
+        data: PathBuf,
+        /// Where to write the tuned weights
+        #[arg(long, value_name = "FILE")]
+        out: PathBuf,
+        /// Weights to start from [default: the built-in ones]
+        #[arg(long, value_name = "FILE")]
+        weights: Option<PathBuf>,
+        /// Passes of gradient descent over the dataset
+        #[arg(long, default_value_t = 500)]
+        iterations: u32,
+        /// How far the weights move per pass, in centipawns
+        #[arg(long, default_value_t = 1.0)]
+        rate: f64,
+    },
+    /// Check every move of a game with the engine and report the mistakes and accuracy of both players
+    Review {
+        #[command(flatten)]
+        position: PositionArgs,
+        /// Depth to search each position to, in half moves
+        #[arg(long, default_value_t = 4)]
+        depth: u32,
+        /// Write the game as PGN annotated with the evaluations to this file
+        #[arg(long, value_name = "FILE")]
+        pgn_out: Option<PathBuf>,
+        /// Evaluation weights to search with, as written by tune [default: the built-in ones]
+        #[arg(long, value_name = "FILE")]
+        weights: Option<PathBuf>,
+    },
+    /// Count the leaf nodes of the move tree to test move generation
+    Perft {
+        depth: u32,
+        /// The position in Forsyth-Edwards Notation, the same as --fen
+        #[arg(id = "position_fen", value_name = "FEN", conflicts_with = "PositionArgs")]
+        fen: Option<String>,
+        #[command(flatten)]
+        position: PositionArgs,
+    },
+    /// Run as an engine speaking the Universal Chess Interface protocol
+    Uci {
+        /// Depth to search to unless the GUI asks for another one
+        #[arg(long, default_value_t = 6)]
+        depth: u32,
+        /// Threads to search with unless the GUI sets the Threads option [default: the number of CPU cores]
+        #[arg(long)]
+        threads: Option<usize>,
+        /// Evaluation weights to search with, as written by tune [default: the built-in ones]
+        #[arg(long, value_name = "FILE")]
+        weights: Option<PathBuf>,
+        /// Play from this Polyglot opening book (.bin) while the position is in it
+        #[arg(long, value_name = "FILE")]
+        book: Option<PathBuf>,
+    },
+    /// Convert a position or game to FEN, PGN or JSON
+    Convert {
+        #[command(flatten)]
+        position: PositionArgs,
+        /// Format to print the result in
+        #[arg(long, value_enum, default_value_t = Format::Pgn)]
+        to: Format,
+        /// Write the game as PGN to this file instead of printing it
+        #[arg(long, value_name = "FILE")]
+        pgn_out: Option<PathBuf>,
+    },
+    /// List the unfinished games that can be resumed
+    Games,
+    /// Learn how the figures move in a few short lessons
+    Tutorial {
+        #[command(flatten)]
+        display: DisplayArgs,
+    },
+}
+
+// Where a command starts from, the initial position if none is given
+#[derive(Args, Clone, Debug)]
+#[group(multiple = false)]
+pub struct PositionArgs {
+    /// Start from a position in Forsyth-Edwards Notation
+    #[arg(long)]
+    pub fen: Option<String>,
+    /// Start from the end of the first game in a PGN file
+    #[arg(long, value_name = "FILE")]
+    pub pgn_in: Option<PathBuf>,
+    /// Continue a saved game, the most recent unfinished one if no id is given
+    #[arg(long, value_name = "ID", num_args = 0..=1, default_missing_value = "")]
+    pub resume: Option<String>,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct PlayArgs {
+    #[command(flatten)]
+    pub position: PositionArgs,
+    /// The side played at the keyboard, the engine plays the other one
+    #[arg(long, value_enum)]
+    pub side: Option<Side>,
+    /// Depth the engine searches to, in half moves
+    #[arg(long)]
+    pub depth: Option<u32>,
+    /// Threads the engine searches with [default: the number of CPU cores]
+    #[arg(long)]
+    pub threads: Option<usize>,
+    /// Evaluation weights the engine uses, as written by tune [default: the built-in ones]
+    #[arg(long, value_name = "FILE")]
+    pub weights: Option<PathBuf>,
+    /// Polyglot opening book (.bin) the engine plays from while the position is in it, its moves are shown beside the board
+    #[arg(long, value_name = "FILE", conflicts_with = "headless")]
+    pub book: Option<PathBuf>,
+    /// Time control as [moves/]minutes[+increment|d delay], stages separated by commas, e.g. 5+3 or 40/90+30,30+30
+    #[arg(long, value_name = "CONTROL", value_parser = parse_time_control)]
+    pub time: Option<TimeControl>,
+    /// Show the engine's evaluation and best line beside the board, following the moves played or browsed
+    #[arg(long)]
+    pub analyze: bool,
+    /// Ask this UCI engine, e.g. stockfish, for hints instead of the built-in one
+    #[arg(long, value_name = "COMMAND")]
+    pub hint_engine: Option<String>,
+    #[command(flatten)]
+    pub display: DisplayArgs,
+    /// Write the game as PGN to this file on exit
+    #[arg(long, value_name = "FILE")]
+    pub pgn_out: Option<PathBuf>,
+    /// Read moves in SAN or UCI notation from stdin instead of opening the board. The exit status is 10 if White won, 20 if Black won, 30 for a draw and 0 if the game has not ended.
+    #[arg(long)]
+    pub headless: bool,
+    /// How the position is printed after each move in headless mode
+    #[arg(long, value_enum, default_value_t = Output::Board, requires = "headless")]
+    pub output: Output,
+}
+
+// How the board looks and is controlled
+#[derive(Args, Clone, Debug)]
+pub struct DisplayArgs {
+    /// Colours of the board: classic, blue, green, high-contrast or a theme from the config file
+    #[arg(long)]
+    pub theme: Option<String>,
+    /// How figures are drawn
+    #[arg(long, value_parser = parse_pieces, value_name = "filled|outline|letters|ascii")]
+    pub pieces: Option<PieceSet>,
+    /// Colours the terminal can show, detected from COLORTERM and TERM by default
+    #[arg(long, value_parser = parse_colors, value_name = "truecolor|256|16|mono")]
+    pub colors: Option<ColorDepth>,
+    /// Key bindings: vim, arrows, wasd or numpad, changed by [bindings] in the config file
+    #[arg(long, value_name = "PRESET")]
+    pub keys: Option<String>,
+    /// Config file to read instead of $XDG_CONFIG_HOME/tress/config.toml
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Output {
+    Board,
+    Fen,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    White,
+    Black,
+    // both sides are played at the keyboard
+    Both,
+    // the engine plays against itself
+    None,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Fen,
+    Pgn,
+    Json,
+}
+
+fn parse_time_control(s: &str) -> Result<TimeControl, String> {
+    s.parse().map_err(|_| format!("invalid time control '{}'", s))
+}
+
+fn parse_colors(s: &str) -> Result<ColorDepth, String> {
+    s.parse().map_err(|_| format!("unknown colour depth '{}'", s))
+}
+
+fn parse_pieces(s: &str) -> Result<PieceSet, String> {
+    s.parse().map_err(|_| format!("unknown piece set '{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn arguments() {
+        Cli::command().debug_assert();
+        // the FEN of perft may be given without --fen
+        let fen = "8/8/8/8/8/8/8/K6k w - - 0 1";
+        let cli = Cli::try_parse_from(["tress", "perft", "2", fen]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Perft {depth: 2, fen: Some(ref f), ..}) if f == fen));
+        assert!(Cli::try_parse_from(["tress", "perft", "2", fen, "--fen", fen]).is_err());
+    }
+}
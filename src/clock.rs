@@ -0,0 +1,214 @@
+use crate::state::Color;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// One period of a time control, e.g. 40 moves in 90 minutes
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Stage {
+    // number of moves to be made in this stage, None for the rest of the game
+    pub moves: Option<u32>,
+    pub time: Duration,
+    // Fischer increment, added after every move
+    pub increment: Duration,
+    // Bronstein delay, the time used for a move is given back up to this much
+    pub delay: Duration,
+}
+
+// A sequence of stages. Once all stages with a move count are used up, the
+// last stage is repeated.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TimeControl {
+    pub stages: Vec<Stage>,
+}
+
+impl TimeControl {
+    pub fn sudden_death(time: Duration) -> Self {
+        Self::fischer(time, Duration::ZERO)
+    }
+
+    pub fn fischer(time: Duration, increment: Duration) -> Self {
+        TimeControl {stages: vec![Stage {moves: None, time, increment, delay: Duration::ZERO}]}
+    }
+
+    pub fn bronstein(time: Duration, delay: Duration) -> Self {
+        TimeControl {stages: vec![Stage {moves: None, time, increment: Duration::ZERO, delay}]}
+    }
+
+    fn stage(&self, index: usize) -> &Stage {
+        &self.stages[index.min(self.stages.len() - 1)]
+    }
+}
+
+fn parse_number(s: &str) -> Result<u64, ()> {
+    s.parse().map_err(|_| ())
+}
+
+// Stages are separated by commas and written as [moves/]minutes[+increment]
+// or [moves/]minutes[d delay], increment and delay in seconds. For example
+// "5+3", "15d10" or "40/90+30,30+30".
+impl FromStr for TimeControl {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut stages = vec![];
+        for stage_s in s.split(',') {
+            let (moves, rest) = match stage_s.split_once('/') {
+                // a stage without moves would never end
+                Some((moves, rest)) => match u32::try_from(parse_number(moves)?) {
+                    Ok(moves) if moves > 0 => (Some(moves), rest),
+                    _ => return Err(()),
+                },
+                None => (None, stage_s),
+            };
+            let (minutes, increment, delay) = if let Some((minutes, increment)) = rest.split_once('+') {
+                (minutes, parse_number(increment)?, 0)
+            } else if let Some((minutes, delay)) = rest.split_once('d') {
+                (minutes, 0, parse_number(delay)?)
+            } else {
+                (rest, 0, 0)
+            };
+            stages.push(Stage {moves,
+                               time: Duration::from_secs(parse_number(minutes)?.checked_mul(60).ok_or(())?),
+                               increment: Duration::from_secs(increment),
+                               delay: Duration::from_secs(delay)});
+        }
+        Ok(TimeControl {stages})
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            if let Some(moves) = stage.moves {
+                write!(f, "{}/", moves)?;
+            }
+            write!(f, "{}", stage.time.as_secs() / 60)?;
+            if !stage.increment.is_zero() {
+                write!(f, "+{}", stage.increment.as_secs())?;
+            }
+            if !stage.delay.is_zero() {
+                write!(f, "d{}", stage.delay.as_secs())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The clocks of both players. Time is only counted through tick, so the
+// clock can be saved and resumed without losing track.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Clock {
+    pub control: TimeControl,
+    remaining: [Duration; 2],
+    // time used by the player to move in the current turn
+    used: Duration,
+    // current stage and moves made in it, per color
+    stage: [usize; 2],
+    stage_moves: [u32; 2],
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        let time = control.stage(0).time;
+        Clock {control,
+               remaining: [time; 2],
+               used: Duration::ZERO,
+               stage: [0; 2],
+               stage_moves: [0; 2]}
+    }
+
+    pub fn remaining(&self, color: Color) -> Duration {
+        self.remaining[color.index()]
+    }
+
+    // Counts elapsed time against the player to move, returns whether their
+    // flag has fallen
+    pub fn tick(&mut self, color: Color, elapsed: Duration) -> bool {
+        let remaining = &mut self.remaining[color.index()];
+        *remaining = remaining.saturating_sub(elapsed);
+        self.used += elapsed;
+        remaining.is_zero()
+    }
+
+    // Called when the given player has made their move
+    pub fn press(&mut self, color: Color) {
+        let i = color.index();
+        let stage = *self.control.stage(self.stage[i]);
+        self.remaining[i] += stage.increment + self.used.min(stage.delay);
+        self.used = Duration::ZERO;
+        self.stage_moves[i] += 1;
+        if stage.moves == Some(self.stage_moves[i]) {
+            // the next stage's time is added to what is left over
+            self.stage[i] += 1;
+            self.stage_moves[i] = 0;
+            self.remaining[i] += self.control.stage(self.stage[i]).time;
+        }
+    }
+}
+
+// Formats a clock reading as h:mm:ss, or m:ss below one hour
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for s in ["5", "5+3", "15d10", "40/90+30,30+30"] {
+            assert_eq!(s.parse::<TimeControl>().unwrap().to_string(), s);
+        }
+        assert!("".parse::<TimeControl>().is_err());
+        assert!("40/".parse::<TimeControl>().is_err());
+        assert!("0/90".parse::<TimeControl>().is_err());
+        assert!("4294967296/90".parse::<TimeControl>().is_err());
+    }
+
+    #[test]
+    fn increment_and_delay() {
+        let mut clock = Clock::new(TimeControl::fischer(secs(60), secs(2)));
+        clock.tick(Color::White, secs(5));
+        clock.press(Color::White);
+        assert_eq!(clock.remaining(Color::White), secs(57));
+
+        let mut clock = Clock::new(TimeControl::bronstein(secs(60), secs(3)));
+        clock.tick(Color::White, secs(2));
+        clock.press(Color::White);
+        assert_eq!(clock.remaining(Color::White), secs(60));
+        clock.tick(Color::Black, secs(5));
+        clock.press(Color::Black);
+        assert_eq!(clock.remaining(Color::Black), secs(58));
+        assert!(clock.tick(Color::White, secs(61)));
+    }
+
+    #[test]
+    fn stages() {
+        let mut clock = Clock::new("2/1,1".parse().unwrap());
+        for _ in 0..2 {
+            clock.tick(Color::White, secs(10));
+            clock.press(Color::White);
+        }
+        assert_eq!(clock.remaining(Color::White), secs(100));
+        assert_eq!(clock.remaining(Color::Black), secs(60));
+    }
+}
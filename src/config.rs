@@ -0,0 +1,253 @@
+use crate::keymap::{self, Action, Key, Keymap};
+use crate::render::{ColorDepth, PieceSet, Theme};
+use ratatui::style;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+// Settings read from config.toml, for example
+//
+//   theme = "mine"
+//   pieces = "outline"
+//   colors = "256"
+//   keys = "arrows"
+//
+//   [bindings]
+//   quit = "ctrl-q"
+//   game_start = ["g g", "home"]
+//
+//   [themes.mine]
+//   base = "blue"
+//   dark_square = "#769656"
+//   white_cursor = "yellow"
+//
+// Colours are names like "light blue", "#rrggbb" or indices into the
+// 256-colour palette. Keys are single characters or names like "space",
+// "esc", "up" or "f1", optionally with "ctrl-" or "alt-" in front, and
+// sequences of them are separated by spaces.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Config {
+    // the theme name and piece set chosen in the file
+    pub theme: Option<String>,
+    pub pieces: Option<PieceSet>,
+    // overrides the colour depth detected from the environment
+    pub colors: Option<ColorDepth>,
+    // themes defined in the file, by name
+    pub themes: HashMap<String, Theme>,
+    // the key preset chosen in the file
+    pub keys: Option<String>,
+    // replace the bindings of the preset, by action
+    pub bindings: HashMap<Action, Vec<Vec<Key>>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    theme: Option<String>,
+    pieces: Option<String>,
+    colors: Option<String>,
+    themes: HashMap<String, ThemeFile>,
+    keys: Option<String>,
+    bindings: HashMap<String, KeysFile>,
+}
+
+// Bindings are a single key sequence or a list of them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeysFile {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeFile {
+    // the preset the theme starts from, classic by default
+    base: Option<String>,
+    pieces: Option<String>,
+    light_square: Option<String>,
+    dark_square: Option<String>,
+    white_figure: Option<String>,
+    black_figure: Option<String>,
+    target: Option<String>,
+    selected: Option<String>,
+    hint: Option<String>,
+    white_cursor: Option<String>,
+    black_cursor: Option<String>,
+}
+
+// Where tress looks for its config, following the XDG base directory
+// specification
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("tress"));
+    }
+    let home = env::var_os("HOME").filter(|home| !home.is_empty())?;
+    Some(PathBuf::from(home).join(".config").join("tress"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+fn parse_pieces(s: &str) -> std::result::Result<PieceSet, String> {
+    s.parse().map_err(|_| format!("unknown piece set '{}'", s))
+}
+
+fn parse_bindings(action: &str, keys: KeysFile) -> std::result::Result<(Action, Vec<Vec<Key>>), String> {
+    let action = action.parse().map_err(|_| format!("unknown action '{}'", action))?;
+    let keys = match keys {
+        KeysFile::One(keys) => vec![keys],
+        KeysFile::Many(keys) => keys,
+    };
+    let sequences = keys
+        .iter()
+        .map(|keys| keymap::parse_sequence(keys).map_err(|_| format!("invalid key '{}'", keys)))
+        .collect::<std::result::Result<_, _>>()?;
+    Ok((action, sequences))
+}
+
+fn parse_theme(name: &str, file: ThemeFile) -> std::result::Result<Theme, String> {
+    let base = file.base.as_deref().unwrap_or("classic");
+    let mut theme = Theme::preset(base).ok_or_else(|| format!("theme {}: unknown preset '{}'", name, base))?;
+    if let Some(pieces) = &file.pieces {
+        theme.pieces = parse_pieces(pieces)?;
+    }
+    let colors = [(&file.light_square, &mut theme.light_square),
+                  (&file.dark_square, &mut theme.dark_square),
+                  (&file.white_figure, &mut theme.white_figure),
+                  (&file.black_figure, &mut theme.black_figure),
+                  (&file.target, &mut theme.target),
+                  (&file.selected, &mut theme.selected),
+                  (&file.hint, &mut theme.hint),
+                  (&file.white_cursor, &mut theme.white_cursor),
+                  (&file.black_cursor, &mut theme.black_cursor)];
+    for (value, color) in colors {
+        if let Some(value) = value {
+            *color = value.parse::<style::Color>()
+                .map_err(|_| format!("theme {}: invalid colour '{}'", name, value))?;
+        }
+    }
+    Ok(theme)
+}
+
+impl Config {
+    pub fn parse(text: &str) -> std::result::Result<Config, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.message().to_owned())?;
+        let mut themes = HashMap::new();
+        for (name, theme) in file.themes {
+            let theme = parse_theme(&name, theme)?;
+            themes.insert(name, theme);
+        }
+        let pieces = match &file.pieces {
+            Some(pieces) => Some(parse_pieces(pieces)?),
+            None => None,
+        };
+        let colors = match &file.colors {
+            Some(colors) => Some(colors.parse().map_err(|_| format!("unknown colour depth '{}'", colors))?),
+            None => None,
+        };
+        if let Some(keys) = &file.keys {
+            Keymap::preset(keys).ok_or_else(|| format!("unknown key preset '{}'", keys))?;
+        }
+        let bindings = file.bindings
+            .into_iter()
+            .map(|(action, keys)| parse_bindings(&action, keys))
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(Config {theme: file.theme, pieces, colors, themes, keys: file.keys, bindings})
+    }
+
+    // Reads the config file. A missing file is no error, as all settings
+    // have defaults.
+    pub fn load(path: &Path) -> Result<Config> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // The theme with the given name, or the one chosen in the file, with the
+    // given piece set or the one chosen in the file. Themes defined in the
+    // file take precedence over presets of the same name.
+    pub fn theme(&self, name: Option<&str>, pieces: Option<PieceSet>) -> Result<Theme> {
+        let name = name.or(self.theme.as_deref()).unwrap_or("classic");
+        let mut theme = self.themes.get(name).copied().or_else(|| Theme::preset(name))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unknown theme '{}'", name)))?;
+        if let Some(pieces) = pieces.or(self.pieces) {
+            theme.pieces = pieces;
+        }
+        Ok(theme)
+    }
+
+    // The key preset with the given name, or the one chosen in the file,
+    // with the bindings from the file applied
+    pub fn keymap(&self, name: Option<&str>) -> Result<Keymap> {
+        let name = name.or(self.keys.as_deref()).unwrap_or("vim");
+        let mut keymap = Keymap::preset(name)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unknown key preset '{}'", name)))?;
+        for (action, sequences) in &self.bindings {
+            keymap.bind(*action, sequences.clone());
+        }
+        Ok(keymap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_theme() {
+        let config = Config::parse("theme = \"mine\"\n\
+                                    pieces = \"letters\"\n\
+                                    [themes.mine]\n\
+                                    base = \"blue\"\n\
+                                    dark_square = \"#769656\"\n\
+                                    white_cursor = \"light yellow\"\n").unwrap();
+        let theme = config.theme(None, None).unwrap();
+        assert_eq!(theme.light_square, Theme::blue().light_square);
+        assert_eq!(theme.dark_square, style::Color::Rgb(118, 150, 86));
+        assert_eq!(theme.white_cursor, style::Color::LightYellow);
+        assert_eq!(theme.pieces, PieceSet::Letters);
+        assert_eq!(config.theme(Some("green"), Some(PieceSet::Ascii)).unwrap().pieces, PieceSet::Ascii);
+        assert_eq!(config.theme(Some("pink"), None).unwrap_err().to_string(), "unknown theme 'pink'");
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(Config::parse("[themes.mine]\ndark_square = \"#76965\"\n").is_err());
+        assert!(Config::parse("[themes.mine]\nbase = \"pink\"\n").is_err());
+        assert!(Config::parse("piece = \"letters\"\n").is_err());
+        assert!(Config::parse("colors = \"8\"\n").is_err());
+        assert_eq!(Config::parse("colors = \"mono\"\n").unwrap().colors, Some(ColorDepth::Monochrome));
+        assert!(Config::parse("keys = \"emacs\"\n").is_err());
+        assert!(Config::parse("[bindings]\njump = \"j\"\n").is_err());
+        assert!(Config::parse("[bindings]\nup = \"foo\"\n").is_err());
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn key_bindings() {
+        let config = Config::parse("keys = \"arrows\"\n\
+                                    [bindings]\n\
+                                    quit = \"ctrl-q\"\n\
+                                    game_start = [\"g g\", \"home\"]\n").unwrap();
+        let keymap = config.keymap(None).unwrap();
+        assert_eq!(keymap.keys(Action::Quit), "ctrl-q");
+        assert_eq!(keymap.keys(Action::GameStart), "g g, home");
+        assert_eq!(keymap.keys(Action::Up), "up");
+        assert_eq!(config.keymap(Some("numpad")).unwrap().keys(Action::Up), "8");
+        assert_eq!(config.keymap(Some("emacs")).unwrap_err().to_string(), "unknown key preset 'emacs'");
+        // a key taken from another action only does what it is bound to
+        let config = Config::parse("keys = \"wasd\"\n[bindings]\ndraw = \"d\"\n").unwrap();
+        let mut keymap = config.keymap(None).unwrap();
+        assert_eq!(keymap.keys(Action::Draw), "d");
+        assert_eq!(keymap.keys(Action::Right), "");
+        assert_eq!(keymap.press("d".parse().unwrap()), Some(Action::Draw));
+    }
+}
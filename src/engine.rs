@@ -0,0 +1,419 @@
+use crate::bitboard;
+use crate::eval::{self, Weights};
+use crate::movelist::MoveFilter;
+use crate::state::{ChessState, Figure, Move};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+// Scores are in centipawns from the point of view of the player to move.
+// Mates are scored as MATE minus the number of half moves to the mate.
+pub const MATE: i32 = 30000;
+const INFINITY: i32 = MATE + 1;
+const MAX_PLY: usize = 64;
+// The transposition table has 2^TABLE_BITS entries of 16 bytes
+const TABLE_BITS: u32 = 18;
+
+pub fn is_mate_score(score: i32) -> bool {
+    score.abs() > MATE - MAX_PLY as i32
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u32,
+    // principal variation, starting with best_move
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+}
+
+fn figure_value(figure: Figure) -> i32 {
+    match figure {
+        Figure::Pawn(_,_) => 100,
+        Figure::Knight(_,_) => 320,
+        Figure::Bishop(_,_) => 330,
+        Figure::Rook(_,_) => 500,
+        Figure::Queen(_,_) => 900,
+        Figure::King(_,_) => 0,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Bound {
+    Exact,
+    // the score is at least this
+    Lower,
+    // the score is at most this
+    Upper,
+}
+
+// What an earlier search found out about a position
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Entry {
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    // the best or refuting move, see encode_move
+    mv: u64,
+}
+
+// The from and to squares and the promotion of a move in 15 bits, zero for
+// no move
+fn encode_move(mv: Move) -> u64 {
+    let promotion = match mv {
+        Move::Promote(_, _, figure, _) => figure.kind_index() as u64 + 1,
+        _ => 0,
+    };
+    let from = bitboard::square(mv.from_pos()) as u64;
+    let to = bitboard::square(mv.to_pos()) as u64;
+    (promotion << 12 | from << 6 | to) + 1
+}
+
+impl Entry {
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        self.score as u32 as u64 | (self.depth.min(255) as u64) << 32 | bound << 40 | self.mv << 42
+    }
+
+    fn unpack(data: u64) -> Self {
+        let bound = match (data >> 40) & 3 {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        Entry {depth: ((data >> 32) & 255) as u32, score: data as u32 as i32, bound, mv: data >> 42}
+    }
+}
+
+// A hash table of searched positions shared by the search threads without
+// locks. Each slot keeps the key xor the data next to the data, so that a
+// slot torn by two threads writing at once does not match any key.
+struct Table {
+    slots: Vec<(AtomicU64, AtomicU64)>,
+}
+
+impl Table {
+    fn new() -> Self {
+        Table {slots: (0..1 << TABLE_BITS).map(|_| (AtomicU64::new(0), AtomicU64::new(0))).collect()}
+    }
+
+    fn slot(&self, key: u64) -> &(AtomicU64, AtomicU64) {
+        &self.slots[(key & ((1 << TABLE_BITS) - 1)) as usize]
+    }
+
+    fn probe(&self, key: u64) -> Option<Entry> {
+        let (check, data) = self.slot(key);
+        let data = data.load(Ordering::Relaxed);
+        if data == 0 || check.load(Ordering::Relaxed) ^ data != key {
+            return None;
+        }
+        Some(Entry::unpack(data))
+    }
+
+    fn store(&self, key: u64, entry: Entry) {
+        let (check, data) = self.slot(key);
+        let packed = entry.pack();
+        check.store(key ^ packed, Ordering::Relaxed);
+        data.store(packed, Ordering::Relaxed);
+    }
+}
+
+// Mate scores count the half moves from the root, in the table they count
+// from the position itself
+fn to_table(score: i32, ply: usize) -> i32 {
+    if is_mate_score(score) { score + score.signum() * ply as i32 } else { score }
+}
+
+fn from_table(score: i32, ply: usize) -> i32 {
+    if is_mate_score(score) { score - score.signum() * ply as i32 } else { score }
+}
+
+struct Searcher<'a> {
+    nodes: u64,
+    table: &'a Table,
+    weights: Arc<Weights>,
+    // triangular principal variation table
+    pv: Vec<Vec<Move>>,
+    // asked now and then whether to give up the search
+    stop: &'a dyn Fn() -> bool,
+    stopped: bool,
+}
+
+// Try promotions and captures of valuable figures with cheap figures first
+fn order_moves(state: &ChessState, moves: &mut [Move]) {
+    moves.sort_by_cached_key(|mv| {
+        let attacker = state.board.fields_get(mv.from_pos()).map_or(0, figure_value);
+        let victim = match mv {
+            Move::EnPassant(_, _, _) => 100,
+            _ => mv.taken().map_or(0, figure_value),
+        };
+        let promotion = match mv {
+            Move::Promote(_, _, fig, _) => figure_value(*fig),
+            _ => 0,
+        };
+        if victim == 0 && promotion == 0 {
+            0
+        } else {
+            -((victim + promotion) * 10 - attacker)
+        }
+    });
+}
+
+impl<'a> Searcher<'a> {
+    fn new(table: &'a Table, weights: Arc<Weights>, stop: &'a dyn Fn() -> bool) -> Self {
+        Searcher {nodes: 0, table, weights, pv: vec![vec![]; MAX_PLY], stop, stopped: false}
+    }
+
+    // Counts a node and checks every few thousand nodes whether to stop
+    fn visit(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(4096) && (self.stop)() {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    fn quiescence(&mut self, state: &mut ChessState, mut alpha: i32, beta: i32) -> i32 {
+        if self.visit() {
+            return 0;
+        }
+        let stand_pat = eval::evaluate(state, &self.weights);
+        if stand_pat >= beta {
+            return beta;
+        }
+        alpha = alpha.max(stand_pat);
+        let mut moves = state.legal_moves_filtered(MoveFilter::Captures);
+        order_moves(state, &mut moves);
+        for mv in moves {
+            state.make_move(mv);
+            let score = -self.quiescence(state, -beta, -alpha);
+            state.unmake_move();
+            if score >= beta {
+                return beta;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+
+    fn negamax(&mut self, state: &mut ChessState, depth: u32, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.pv[ply].clear();
+        if depth == 0 || ply + 1 >= MAX_PLY {
+            return self.quiescence(state, alpha, beta);
+        }
+        if self.visit() {
+            return 0;
+        }
+        let key = state.search_hash();
+        let entry = self.table.probe(key);
+        // the root is always searched, so that there is a best move
+        if let Some(entry) = entry.filter(|entry| ply > 0 && entry.depth >= depth) {
+            let score = from_table(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return score.clamp(alpha, beta),
+                Bound::Lower if score >= beta => return beta,
+                Bound::Upper if score <= alpha => return alpha,
+                _ => (),
+            }
+        }
+        let mut moves = state.legal_moves();
+        if moves.is_empty() {
+            return if state.player_is_in_check() { -MATE + ply as i32 } else { 0 };
+        }
+        if state.halfmove_clock >= 100 {
+            return 0;
+        }
+        order_moves(state, &mut moves);
+        // the move found best before goes first
+        if let Some(i) = entry.and_then(|entry| moves.iter().position(|mv| encode_move(*mv) == entry.mv)) {
+            moves[..=i].rotate_right(1);
+        }
+        let mut best = 0;
+        let mut bound = Bound::Upper;
+        for mv in moves {
+            state.make_move(mv);
+            let score = -self.negamax(state, depth - 1, ply + 1, -beta, -alpha);
+            state.unmake_move();
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                self.table.store(key, Entry {depth, score: to_table(beta, ply), bound: Bound::Lower, mv: encode_move(mv)});
+                return beta;
+            }
+            if score > alpha {
+                alpha = score;
+                best = encode_move(mv);
+                bound = Bound::Exact;
+                let (head, tail) = self.pv.split_at_mut(ply + 1);
+                head[ply].clear();
+                head[ply].push(mv);
+                head[ply].extend_from_slice(&tail[0]);
+            }
+        }
+        self.table.store(key, Entry {depth, score: to_table(alpha, ply), bound, mv: best});
+        alpha
+    }
+}
+
+// Searches the position with iterative deepening up to the given depth,
+// evaluating positions with the weights
+pub fn search(state: &mut ChessState, depth: u32, weights: Arc<Weights>) -> SearchResult {
+    search_until(state, depth, weights, &|| false, |_| ())
+}
+
+// Like search, but gives up once stop returns true and passes the result of
+// each finished depth to report. An unfinished depth is not reported.
+pub fn search_until(state: &mut ChessState,
+                    depth: u32,
+                    weights: Arc<Weights>,
+                    stop: &dyn Fn() -> bool,
+                    report: impl FnMut(&SearchResult)) -> SearchResult {
+    search_threads(state, depth, 1, weights, stop, report)
+}
+
+// Like search_until, with helper threads searching the same position until
+// the main search is done (Lazy SMP). They share what they found through the
+// transposition table, so the main search gets deeper faster.
+pub fn search_threads(state: &mut ChessState,
+                      depth: u32,
+                      threads: usize,
+                      weights: Arc<Weights>,
+                      stop: &dyn Fn() -> bool,
+                      mut report: impl FnMut(&SearchResult)) -> SearchResult {
+    let table = Table::new();
+    let done = AtomicBool::new(false);
+    let helper_nodes = AtomicU64::new(0);
+    thread::scope(|scope| {
+        for helper in 1..threads.max(1) {
+            let mut state = state.clone();
+            let weights = weights.clone();
+            let (table, done, helper_nodes) = (&table, &done, &helper_nodes);
+            scope.spawn(move || {
+                let stop = || done.load(Ordering::Relaxed);
+                let mut searcher = Searcher::new(table, weights, &stop);
+                // every other helper keeps a depth ahead, so that the
+                // threads do not all search the same nodes
+                let mut d = 1 + helper as u32 % 2;
+                while !searcher.stopped && (d as usize) < MAX_PLY {
+                    let nodes = searcher.nodes;
+                    searcher.negamax(&mut state, d, 0, -INFINITY, INFINITY);
+                    helper_nodes.fetch_add(searcher.nodes - nodes, Ordering::Relaxed);
+                    d += 1;
+                }
+            });
+        }
+
+        let mut searcher = Searcher::new(&table, weights, stop);
+        let mut result = SearchResult {best_move: None, score: 0, depth: 0, pv: vec![], nodes: 0};
+        for d in 1..=depth.max(1) {
+            let score = searcher.negamax(state, d, 0, -INFINITY, INFINITY);
+            if searcher.stopped {
+                break;
+            }
+            result = SearchResult {best_move: searcher.pv[0].first().copied(),
+                                   score,
+                                   depth: d,
+                                   pv: searcher.pv[0].clone(),
+                                   nodes: searcher.nodes + helper_nodes.load(Ordering::Relaxed)};
+            report(&result);
+            if is_mate_score(score) {
+                break;
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+// Searches a position in a background thread, so that the user interface
+// keeps running while the engine thinks. The search is given up when this
+// is dropped.
+pub struct Thinking {
+    state: ChessState,
+    result: Receiver<SearchResult>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Thinking {
+    pub fn start(state: &ChessState, depth: u32, threads: usize, weights: Arc<Weights>) -> Self {
+        let (sender, result) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let stop = cancelled.clone();
+        let mut searched = state.clone();
+        thread::spawn(move || {
+            let result = search_threads(&mut searched, depth, threads, weights, &|| stop.load(Ordering::Relaxed), |_| ());
+            let _ = sender.send(result);
+        });
+        Thinking {state: state.clone(), result, cancelled}
+    }
+
+    // The position being searched
+    pub fn state(&self) -> &ChessState {
+        &self.state
+    }
+
+    // The result once the search is done
+    pub fn result(&self) -> Option<SearchResult> {
+        self.result.try_recv().ok()
+    }
+}
+
+impl Drop for Thinking {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Color;
+
+    #[test]
+    fn finds_mate_in_one() {
+        let mut state = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let result = search(&mut state, 3, Arc::new(Weights::default()));
+        assert_eq!(result.best_move.unwrap().to_string(), "a1a8");
+        assert_eq!(result.score, MATE - 1);
+    }
+
+    #[test]
+    fn packs_entries() {
+        let mv = Move::Promote("b7".parse().unwrap(), "a8".parse().unwrap(), Figure::Knight(Color::White, true), None);
+        for entry in [Entry {depth: 3, score: -MATE + 5, bound: Bound::Upper, mv: encode_move(mv)},
+                      Entry {depth: 12, score: 35, bound: Bound::Exact, mv: 0}] {
+            assert_eq!(Entry::unpack(entry.pack()), entry);
+        }
+        assert_ne!(encode_move(mv), encode_move(Move::Promote("b7".parse().unwrap(), "a8".parse().unwrap(),
+                                                              Figure::Queen(Color::White, true), None)));
+    }
+
+    #[test]
+    fn helper_threads() {
+        let mut state = ChessState::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 4 4").unwrap();
+        let result = search_threads(&mut state, 3, 4, Arc::new(Weights::default()), &|| false, |_| ());
+        assert_eq!(result.best_move.unwrap().to_string(), "f3f7");
+        assert_eq!(result.score, MATE - 1);
+        // a search stopped right away still finishes the threads
+        let result = search_threads(&mut state, 8, 4, Arc::new(Weights::default()), &|| true, |_| ());
+        assert!(result.depth < 8);
+    }
+
+    #[test]
+    fn takes_hanging_queen() {
+        let mut state = ChessState::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let result = search(&mut state, 2, Arc::new(Weights::default()));
+        assert_eq!(result.best_move.unwrap().to_string(), "d2d5");
+        // the search evaluates with the weights it is given
+        let mut weights = Weights::default();
+        weights.values_mut()[eval::MATERIAL + crate::state::ROOK] = eval::Score::default();
+        assert!(search(&mut state, 2, Arc::new(weights)).score < result.score);
+    }
+}
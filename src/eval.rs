@@ -0,0 +1,595 @@
+use crate::bitboard;
+use crate::state::{ChessState, Color, Figure, BISHOP, KING, KNIGHT, PAWN, QUEEN, ROOK};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
+
+// The game phase goes from this with all figures on the board down to zero
+// without Knights, Bishops, Rooks and Queens
+pub const MAX_PHASE: i32 = 24;
+
+// A value in the middlegame and in the endgame, in centipawns. Positions
+// in between get a mix of both by the phase of the game.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    pub const fn new(mg: i32, eg: i32) -> Self {
+        Score {mg, eg}
+    }
+
+    pub fn taper(&self, phase: i32) -> i32 {
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+
+    fn add(self, other: Score) -> Score {
+        Score::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+
+    fn sub(self, other: Score) -> Score {
+        Score::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other: Score) {
+        *self = *self - other;
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+
+    fn mul(self, count: i32) -> Score {
+        Score::new(self.mg * count, self.eg * count)
+    }
+}
+
+// The parts the evaluation is made of
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Term {
+    Material,
+    // where the figures stand
+    Squares,
+    // how many squares the figures reach
+    Mobility,
+    // attacks next to the King and the Pawns in front of it
+    KingSafety,
+    DoubledPawns,
+    IsolatedPawns,
+    PassedPawns,
+    BishopPair,
+}
+
+impl Term {
+    pub const ALL: [Term; 8] = [Term::Material, Term::Squares, Term::Mobility, Term::KingSafety,
+                                Term::DoubledPawns, Term::IsolatedPawns, Term::PassedPawns, Term::BishopPair];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Term::Material => "material",
+            Term::Squares => "piece squares",
+            Term::Mobility => "mobility",
+            Term::KingSafety => "king safety",
+            Term::DoubledPawns => "doubled pawns",
+            Term::IsolatedPawns => "isolated pawns",
+            Term::PassedPawns => "passed pawns",
+            Term::BishopPair => "bishop pair",
+        }
+    }
+}
+
+// Where each weight is found in Weights::values. Figures are in the order
+// of Figure::kind_index, squares from a8 to h1 as seen from the side of the
+// figure, passed Pawns by their rank from that side.
+pub(crate) const MATERIAL: usize = 0;
+const SQUARES: usize = MATERIAL + 6;
+const MOBILITY: usize = SQUARES + 6 * 64;
+const KING_ATTACK: usize = MOBILITY + 6;
+const PAWN_SHIELD: usize = KING_ATTACK + 1;
+const DOUBLED_PAWN: usize = PAWN_SHIELD + 1;
+const ISOLATED_PAWN: usize = DOUBLED_PAWN + 1;
+const PASSED_PAWN: usize = ISOLATED_PAWN + 1;
+const BISHOP_PAIR: usize = PASSED_PAWN + 8;
+const WEIGHTS: usize = BISHOP_PAIR + 1;
+
+// The weights of the evaluation terms, one Score per feature of a position
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Weights {
+    values: Vec<Score>,
+}
+
+// Piece-square tables from White's side, a8 first like a diagram
+#[rustfmt::skip]
+const PAWN_SQUARES: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     50,  50,  50,  50,  50,  50,  50,  50,
+     10,  10,  20,  30,  30,  20,  10,  10,
+      5,   5,  10,  25,  25,  10,   5,   5,
+      0,   0,   0,  20,  20,   0,   0,   0,
+      5,  -5, -10,   0,   0, -10,  -5,   5,
+      5,  10,  10, -20, -20,  10,  10,   5,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_SQUARES: [i32; 64] = [
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20,   0,   0,   0,   0, -20, -40,
+    -30,   0,  10,  15,  15,  10,   0, -30,
+    -30,   5,  15,  20,  20,  15,   5, -30,
+    -30,   0,  15,  20,  20,  15,   0, -30,
+    -30,   5,  10,  15,  15,  10,   5, -30,
+    -40, -20,   0,   5,   5,   0, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+#[rustfmt::skip]
+const BISHOP_SQUARES: [i32; 64] = [
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   5,   5,  10,  10,   5,   5, -10,
+    -10,   0,  10,  10,  10,  10,   0, -10,
+    -10,  10,  10,  10,  10,  10,  10, -10,
+    -10,   5,   0,   0,   0,   0,   5, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+#[rustfmt::skip]
+const ROOK_SQUARES: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+      5,  10,  10,  10,  10,  10,  10,   5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+      0,   0,   0,   5,   5,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const QUEEN_SQUARES: [i32; 64] = [
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+      0,   0,   5,   5,   5,   5,   0,  -5,
+    -10,   5,   5,   5,   5,   5,   0, -10,
+    -10,   0,   5,   0,   0,   0,   0, -10,
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+// the King hides in the middlegame and comes out in the endgame
+#[rustfmt::skip]
+const KING_SQUARES: [i32; 64] = [
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -20, -30, -30, -40, -40, -30, -30, -20,
+    -10, -20, -20, -20, -20, -20, -20, -10,
+     20,  20,   0,   0,   0,   0,  20,  20,
+     20,  30,  10,   0,   0,  10,  30,  20,
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME_SQUARES: [i32; 64] = [
+    -50, -40, -30, -20, -20, -30, -40, -50,
+    -30, -20, -10,   0,   0, -10, -20, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -30,   0,   0,   0,   0, -30, -30,
+    -50, -30, -30, -30, -30, -30, -30, -50,
+];
+
+impl Default for Weights {
+    fn default() -> Self {
+        let mut values = vec![Score::default(); WEIGHTS];
+        let figures = [(PAWN, Score::new(100, 120), &PAWN_SQUARES, &PAWN_SQUARES),
+                       (KNIGHT, Score::new(320, 300), &KNIGHT_SQUARES, &KNIGHT_SQUARES),
+                       (BISHOP, Score::new(330, 310), &BISHOP_SQUARES, &BISHOP_SQUARES),
+                       (ROOK, Score::new(500, 530), &ROOK_SQUARES, &ROOK_SQUARES),
+                       (QUEEN, Score::new(900, 940), &QUEEN_SQUARES, &QUEEN_SQUARES),
+                       (KING, Score::new(0, 0), &KING_SQUARES, &KING_ENDGAME_SQUARES)];
+        for (kind, material, mg, eg) in figures {
+            values[MATERIAL + kind] = material;
+            for square in 0..64 {
+                values[SQUARES + kind * 64 + square] = Score::new(mg[square], eg[square]);
+            }
+        }
+        values[MOBILITY + KNIGHT] = Score::new(4, 4);
+        values[MOBILITY + BISHOP] = Score::new(5, 5);
+        values[MOBILITY + ROOK] = Score::new(2, 4);
+        values[MOBILITY + QUEEN] = Score::new(1, 2);
+        values[KING_ATTACK] = Score::new(-8, -2);
+        values[PAWN_SHIELD] = Score::new(10, 0);
+        values[DOUBLED_PAWN] = Score::new(-10, -20);
+        values[ISOLATED_PAWN] = Score::new(-10, -15);
+        let passed = [(0, 0), (0, 5), (5, 10), (10, 20), (20, 40), (35, 70), (60, 110), (0, 0)];
+        for (rank, (mg, eg)) in passed.into_iter().enumerate() {
+            values[PASSED_PAWN + rank] = Score::new(mg, eg);
+        }
+        values[BISHOP_PAIR] = Score::new(30, 50);
+        Weights {values}
+    }
+}
+
+impl Weights {
+    // All weights in one list, for tuning them
+    pub fn values(&self) -> &[Score] {
+        &self.values
+    }
+
+    pub fn values_mut(&mut self) -> &mut [Score] {
+        &mut self.values
+    }
+}
+
+// How far the game is from the endgame, by the figures other than Pawns
+// and Kings on the board
+pub fn phase(state: &ChessState) -> i32 {
+    let phase: i32 = state.board.fields.iter().flatten().flatten().map(|figure| match figure {
+        Figure::Knight(_,_) | Figure::Bishop(_,_) => 1,
+        Figure::Rook(_,_) => 2,
+        Figure::Queen(_,_) => 4,
+        Figure::Pawn(_,_) | Figure::King(_,_) => 0,
+    }).sum();
+    phase.min(MAX_PHASE)
+}
+
+// The features of the position: calls add with the term, the side it
+// counts for, the index of the weight in Weights::values and how often the
+// feature occurs
+pub fn features(state: &ChessState, mut add: impl FnMut(Term, Color, usize, i32)) {
+    let board = &state.board;
+    let fields = &board.fields;
+    let occupied = board.occupied();
+    // the fields are indexed by file and by row from the top, rank 8 first
+    let row_from_side = |color: Color, row: usize| match color {
+        Color::White => row,
+        Color::Black => 7 - row,
+    };
+    let is_pawn = |x: usize, y: usize, color: Color| matches!(fields[x][y], Some(Figure::Pawn(c, _)) if c == color);
+
+    let mut pawns = [[0; 8]; 2];
+    let mut bishops = [0; 2];
+    for (x, file) in fields.iter().enumerate() {
+        for figure in file.iter().flatten() {
+            match figure {
+                Figure::Pawn(color, _) => pawns[color.index()][x] += 1,
+                Figure::Bishop(color, _) => bishops[color.index()] += 1,
+                _ => (),
+            }
+        }
+    }
+    let king_zones = [Color::White, Color::Black].map(|color| {
+        board.king_square(color).map_or(0, |square| bitboard::king_attacks(square) | bitboard::bit(square))
+    });
+
+    for (x, file) in fields.iter().enumerate() {
+        for (y, figure) in file.iter().enumerate() {
+            let figure = match figure {
+                Some(figure) => *figure,
+                None => continue,
+            };
+            let color = figure.color();
+            let kind = figure.kind_index();
+            let row = row_from_side(color, y);
+            add(Term::Material, color, MATERIAL + kind, 1);
+            add(Term::Squares, color, SQUARES + kind * 64 + row * 8 + x, 1);
+
+            let square = (7 - y) * 8 + x;
+            let attacks = match kind {
+                KNIGHT => bitboard::knight_attacks(square),
+                BISHOP => bitboard::bishop_attacks(square, occupied),
+                ROOK => bitboard::rook_attacks(square, occupied),
+                QUEEN => bitboard::queen_attacks(square, occupied),
+                PAWN => bitboard::pawn_attacks(color, square),
+                _ => 0,
+            };
+            if kind != PAWN && kind != KING {
+                let mobility = (attacks & !board.color_pieces(color)).count_ones() as i32;
+                add(Term::Mobility, color, MOBILITY + kind, mobility);
+            }
+            let king_attacks = (attacks & king_zones[color.other().index()]).count_ones() as i32;
+            if king_attacks > 0 {
+                add(Term::KingSafety, color.other(), KING_ATTACK, king_attacks);
+            }
+
+            if kind == KING {
+                // the Pawns one and two squares in front of the King
+                let ahead = match color {
+                    Color::White => y.saturating_sub(2)..y,
+                    Color::Black => y + 1..(y + 3).min(8),
+                };
+                let shield = (x.saturating_sub(1)..=(x + 1).min(7))
+                    .flat_map(|file| ahead.clone().map(move |row| (file, row)))
+                    .filter(|&(file, row)| is_pawn(file, row, color))
+                    .count() as i32;
+                if shield > 0 {
+                    add(Term::KingSafety, color, PAWN_SHIELD, shield);
+                }
+            }
+
+            if kind == PAWN {
+                let files = x.saturating_sub(1)..=(x + 1).min(7);
+                if files.clone().all(|file| file == x || pawns[color.index()][file] == 0) {
+                    add(Term::IsolatedPawns, color, ISOLATED_PAWN, 1);
+                }
+                // no Pawn of the opponent in front of it or next to that,
+                // and of doubled Pawns only the front one
+                let ahead = match color {
+                    Color::White => 0..y,
+                    Color::Black => y + 1..8,
+                };
+                let blocked = ahead.clone().any(|row| is_pawn(x, row, color));
+                if !blocked && !files.flat_map(|file| ahead.clone().map(move |row| (file, row)))
+                    .any(|(file, row)| is_pawn(file, row, color.other())) {
+                    add(Term::PassedPawns, color, PASSED_PAWN + 7 - row, 1);
+                }
+            }
+        }
+    }
+
+    for color in [Color::White, Color::Black] {
+        for count in pawns[color.index()] {
+            if count > 1 {
+                add(Term::DoubledPawns, color, DOUBLED_PAWN, count - 1);
+            }
+        }
+        if bishops[color.index()] >= 2 {
+            add(Term::BishopPair, color, BISHOP_PAIR, 1);
+        }
+    }
+}
+
+// The evaluation from the point of view of the player to move
+pub fn evaluate(state: &ChessState, weights: &Weights) -> i32 {
+    let mut score = Score::default();
+    features(state, |_, color, i, count| match color {
+        Color::White => score += weights.values[i] * count,
+        Color::Black => score -= weights.values[i] * count,
+    });
+    let score = score.taper(phase(state));
+    match state.turn {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
+
+// Each term of the evaluation for White and for Black, the evaluation is
+// their difference
+pub fn breakdown(state: &ChessState, weights: &Weights) -> Vec<(Term, [Score; 2])> {
+    let mut terms: Vec<(Term, [Score; 2])> = Term::ALL.iter().map(|term| (*term, [Score::default(); 2])).collect();
+    features(state, |term, color, i, count| {
+        let (_, scores) = terms.iter_mut().find(|(t, _)| *t == term).unwrap();
+        scores[color.index()] += weights.values[i] * count;
+    });
+    terms
+}
+
+// The weights file in TOML, for example
+//
+//   bishop_pair = [30, 50]
+//
+//   [material]
+//   pawn = [100, 120]
+//   ...
+//
+//   [squares.pawn]
+//   mg = [0, 0, 0, 0, 0, 0, 0, 0, 50, 50, ...]
+//   eg = [...]
+//
+// with every value a pair of middlegame and endgame centipawns and the
+// squares listed from a8 to h1 as seen from the figure's side. Weights left
+// out keep their default.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct WeightsFile {
+    king_attack: [i32; 2],
+    pawn_shield: [i32; 2],
+    doubled_pawn: [i32; 2],
+    isolated_pawn: [i32; 2],
+    bishop_pair: [i32; 2],
+    // from the second to the seventh rank, the first and last are unused
+    passed_pawn: Vec<[i32; 2]>,
+    material: Figures<[i32; 2]>,
+    mobility: Figures<[i32; 2]>,
+    squares: Figures<SquaresFile>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Figures<T> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pawn: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    knight: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bishop: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rook: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queen: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    king: Option<T>,
+}
+
+#[cfg(feature = "serde")]
+impl<T> Figures<T> {
+    fn from_fn(mut f: impl FnMut(usize) -> T) -> Self {
+        Figures {pawn: Some(f(PAWN)),
+                 knight: Some(f(KNIGHT)),
+                 bishop: Some(f(BISHOP)),
+                 rook: Some(f(ROOK)),
+                 queen: Some(f(QUEEN)),
+                 king: Some(f(KING))}
+    }
+
+    // The figures that were given
+    fn into_vec(self) -> Vec<(usize, &'static str, Option<T>)> {
+        vec![(PAWN, "pawn", self.pawn),
+             (KNIGHT, "knight", self.knight),
+             (BISHOP, "bishop", self.bishop),
+             (ROOK, "rook", self.rook),
+             (QUEEN, "queen", self.queen),
+             (KING, "king", self.king)]
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SquaresFile {
+    mg: Vec<i32>,
+    eg: Vec<i32>,
+}
+
+#[cfg(feature = "serde")]
+impl Default for WeightsFile {
+    fn default() -> Self {
+        WeightsFile::from(&Weights::default())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<&Weights> for WeightsFile {
+    fn from(weights: &Weights) -> Self {
+        let pair = |i: usize| [weights.values[i].mg, weights.values[i].eg];
+        WeightsFile {king_attack: pair(KING_ATTACK),
+                     pawn_shield: pair(PAWN_SHIELD),
+                     doubled_pawn: pair(DOUBLED_PAWN),
+                     isolated_pawn: pair(ISOLATED_PAWN),
+                     bishop_pair: pair(BISHOP_PAIR),
+                     passed_pawn: (0..8).map(|rank| pair(PASSED_PAWN + rank)).collect(),
+                     material: Figures::from_fn(|kind| pair(MATERIAL + kind)),
+                     mobility: Figures::from_fn(|kind| pair(MOBILITY + kind)),
+                     squares: Figures::from_fn(|kind| {
+                         let table = &weights.values[SQUARES + kind * 64..SQUARES + kind * 64 + 64];
+                         SquaresFile {mg: table.iter().map(|score| score.mg).collect(),
+                                      eg: table.iter().map(|score| score.eg).collect()}
+                     })}
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Weights {
+    pub fn parse(text: &str) -> Result<Weights, String> {
+        let file: WeightsFile = toml::from_str(text).map_err(|e| e.message().to_owned())?;
+        let mut values = Weights::default().values;
+        let pair = |[mg, eg]: [i32; 2]| Score::new(mg, eg);
+        values[KING_ATTACK] = pair(file.king_attack);
+        values[PAWN_SHIELD] = pair(file.pawn_shield);
+        values[DOUBLED_PAWN] = pair(file.doubled_pawn);
+        values[ISOLATED_PAWN] = pair(file.isolated_pawn);
+        values[BISHOP_PAIR] = pair(file.bishop_pair);
+        if file.passed_pawn.len() != 8 {
+            return Err("passed_pawn needs a value for each of the 8 ranks".to_owned());
+        }
+        for (rank, value) in file.passed_pawn.into_iter().enumerate() {
+            values[PASSED_PAWN + rank] = pair(value);
+        }
+        for (kind, _, value) in file.material.into_vec() {
+            if let Some(value) = value {
+                values[MATERIAL + kind] = pair(value);
+            }
+        }
+        for (kind, _, value) in file.mobility.into_vec() {
+            if let Some(value) = value {
+                values[MOBILITY + kind] = pair(value);
+            }
+        }
+        for (kind, name, table) in file.squares.into_vec() {
+            let table = match table {
+                Some(table) => table,
+                None => continue,
+            };
+            if table.mg.len() != 64 || table.eg.len() != 64 {
+                return Err(format!("squares.{} needs 64 values for mg and eg", name));
+            }
+            for square in 0..64 {
+                values[SQUARES + kind * 64 + square] = Score::new(table.mg[square], table.eg[square]);
+            }
+        }
+        Ok(Weights {values})
+    }
+
+    pub fn load(path: &std::path::Path) -> std::io::Result<Weights> {
+        let text = std::fs::read_to_string(path)?;
+        Weights::parse(&text)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(&WeightsFile::from(self)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symmetric() {
+        let weights = Weights::default();
+        assert_eq!(evaluate(&ChessState::new(), &weights), 0);
+        assert_eq!(phase(&ChessState::new()), MAX_PHASE);
+        // the same position with the colours swapped
+        let white = ChessState::from_fen("r3k2r/pp3ppp/2n5/3p4/1b1P4/2N2N2/PP3PPP/R2QK2R w KQkq - 0 1").unwrap();
+        let black = ChessState::from_fen("r2qk2r/pp3ppp/2n2n2/1B1p4/3P4/2N5/PP3PPP/R3K2R b KQkq - 0 1").unwrap();
+        assert_eq!(evaluate(&white, &weights), evaluate(&black, &weights));
+    }
+
+    #[test]
+    fn pawn_structure() {
+        // White has doubled and isolated Pawns on the e file, Black a passed
+        // Pawn on a3
+        let state = ChessState::from_fen("4k3/8/8/8/4P3/p3P3/8/4K3 w - - 0 1").unwrap();
+        let terms = breakdown(&state, &Weights::default());
+        let term = |term: Term| terms.iter().find(|(t, _)| *t == term).unwrap().1;
+        assert_eq!(term(Term::DoubledPawns), [Score::new(-10, -20), Score::default()]);
+        assert_eq!(term(Term::IsolatedPawns), [Score::new(-20, -30), Score::new(-10, -15)]);
+        // only the front one of the doubled Pawns is passed
+        assert_eq!(term(Term::PassedPawns), [Score::new(10, 20), Score::new(35, 70)]);
+        assert_eq!(phase(&state), 0);
+        let total: Score = terms.iter().map(|(_, [white, black])| *white - *black).fold(Score::default(), Add::add);
+        assert_eq!(evaluate(&state, &Weights::default()), total.eg);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn weights_file() {
+        let mut weights = Weights::default();
+        weights.values_mut()[BISHOP_PAIR] = Score::new(40, 60);
+        assert_eq!(Weights::parse(&weights.to_toml()).unwrap(), weights);
+        let weights = Weights::parse("bishop_pair = [40, 60]\n[material]\npawn = [90, 110]").unwrap();
+        assert_eq!(weights.values()[BISHOP_PAIR], Score::new(40, 60));
+        assert_eq!(weights.values()[MATERIAL + PAWN], Score::new(90, 110));
+        assert_eq!(weights.values()[MATERIAL + KNIGHT], Weights::default().values()[MATERIAL + KNIGHT]);
+        assert_eq!(weights.values()[MOBILITY + KNIGHT], Weights::default().values()[MOBILITY + KNIGHT]);
+        assert!(Weights::parse("passed_pawn = [[1, 2]]").is_err());
+        assert!(Weights::parse("bishop_pairs = [40, 60]").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::notation;
+use crate::state::{ChessState, Move};
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long an engine may take to start and get ready
+const START_TIME: Duration = Duration::from_secs(5);
+// How much longer than asked an engine may think before it is given up
+const MOVE_TIME_MARGIN: Duration = Duration::from_secs(2);
+// How often a search is checked for being given up
+const STOP_CHECK: Duration = Duration::from_millis(20);
+
+// A chess engine in another process, like Stockfish, spoken to over the
+// Universal Chess Interface protocol
+pub struct ExternalEngine {
+    process: Child,
+    input: ChildStdin,
+    // the lines the engine writes, read in a thread so that waiting for
+    // them can time out
+    output: Receiver<io::Result<String>>,
+    // as the engine calls itself
+    pub name: String,
+}
+
+impl ExternalEngine {
+    // Starts the engine and waits until it is ready
+    pub fn start(command: Command) -> io::Result<Self> {
+        Self::start_within(command, START_TIME)
+    }
+
+    fn start_within(mut command: Command, time: Duration) -> io::Result<Self> {
+        let mut process = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let input = process.stdin.take().unwrap();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+        let (sender, output) = mpsc::channel();
+        // ends when the engine closes its output or the engine is dropped
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut engine = ExternalEngine {process, input, output, name: String::new()};
+        let deadline = Instant::now() + time;
+        engine.send("uci")?;
+        engine.wait_for(deadline, &|| false, |engine, line| {
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_owned();
+            }
+            line == "uciok"
+        })?;
+        engine.send("isready")?;
+        engine.wait_for(deadline, &|| false, |_, line| line == "readyok")?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.input, "{}", command)?;
+        self.input.flush()
+    }
+
+    // Reads lines until done returns true for one, failing if none does
+    // before the deadline or stop returns true
+    fn wait_for(&mut self,
+                deadline: Instant,
+                stop: &dyn Fn() -> bool,
+                mut done: impl FnMut(&mut Self, &str) -> bool) -> io::Result<String> {
+        loop {
+            if stop() {
+                return Err(Error::new(ErrorKind::Interrupted, "the search was given up"));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            let line = match self.output.recv_timeout(left.min(STOP_CHECK)) {
+                Ok(line) => line?,
+                Err(RecvTimeoutError::Timeout) if left > STOP_CHECK => continue,
+                Err(RecvTimeoutError::Timeout) =>
+                    return Err(Error::new(ErrorKind::TimedOut, "the engine did not answer in time")),
+                Err(RecvTimeoutError::Disconnected) =>
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "the engine quit")),
+            };
+            let line = line.trim();
+            if done(self, line) {
+                return Ok(line.to_owned());
+            }
+        }
+    }
+
+    // Lets the engine think about the position for the given time, None if
+    // the player to move has no moves
+    pub fn best_move(&mut self, state: &ChessState, time: Duration) -> io::Result<Option<Move>> {
+        self.best_move_until(state, time, &|| false)
+    }
+
+    // Like best_move, but gives up once stop returns true
+    pub fn best_move_until(&mut self,
+                           state: &ChessState,
+                           time: Duration,
+                           stop: &dyn Fn() -> bool) -> io::Result<Option<Move>> {
+        // the moves are sent as well, so that the engine knows about
+        // repetitions
+        let mut start = state.clone();
+        while start.unmake_move().is_some() {}
+        let moves: Vec<String> = state.history().map(|mv| mv.to_string()).collect();
+        let mut position = format!("position fen {}", start.to_fen());
+        if !moves.is_empty() {
+            position.push_str(" moves ");
+            position.push_str(&moves.join(" "));
+        }
+        // forget the answer to an earlier search that was given up
+        while self.output.try_recv().is_ok() {}
+        self.send(&position)?;
+        self.send(&format!("go movetime {}", time.as_millis()))?;
+        let deadline = Instant::now() + time + MOVE_TIME_MARGIN;
+        let line = match self.wait_for(deadline, stop, |_, line| line.starts_with("bestmove")) {
+            Ok(line) => line,
+            Err(e) => {
+                // the answer to the search given up is not wanted
+                if self.send("stop").is_ok() && e.kind() == ErrorKind::Interrupted {
+                    let _ = self.wait_for(Instant::now() + MOVE_TIME_MARGIN, &|| false,
+                                          |_, line| line.starts_with("bestmove"));
+                }
+                return Err(e);
+            },
+        };
+        match line.split_whitespace().nth(1) {
+            None | Some("(none)") | Some("0000") => Ok(None),
+            Some(mv) => notation::parse_uci(state, mv)
+                .map(Some)
+                .map_err(|_| Error::new(ErrorKind::InvalidData, format!("the engine played an illegal move '{}'", mv))),
+        }
+    }
+}
+
+impl Drop for ExternalEngine {
+    fn drop(&mut self) {
+        // give the engine a moment to quit on its own
+        if self.send("quit").is_ok() {
+            for _ in 0..10 {
+                if let Ok(Some(_)) = self.process.try_wait() {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+// Asks an engine for moves in a background thread, so that the user
+// interface keeps running while it thinks. Asking about another position
+// gives up the search for the last one, as does dropping this.
+pub struct BackgroundEngine {
+    positions: Sender<(u64, ChessState)>,
+    answers: Receiver<(u64, io::Result<Option<Move>>)>,
+    // counts the positions asked about, the search stops when it changes
+    generation: Arc<AtomicU64>,
+    // the position asked about, until it is answered
+    position: Option<ChessState>,
+    pub name: String,
+}
+
+impl BackgroundEngine {
+    // Lets the engine think about each position for the given time
+    pub fn new(mut engine: ExternalEngine, time: Duration) -> Self {
+        let name = engine.name.clone();
+        let (positions, position_receiver) = mpsc::channel::<(u64, ChessState)>();
+        let (answer_sender, answers) = mpsc::channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let current = generation.clone();
+        // ends, and quits the engine, when this is dropped
+        thread::spawn(move || {
+            while let Ok(mut position) = position_receiver.recv() {
+                // only the latest position is of interest
+                while let Ok(newer) = position_receiver.try_recv() {
+                    position = newer;
+                }
+                let (id, state) = position;
+                let stop = || current.load(Ordering::Relaxed) != id;
+                let answer = engine.best_move_until(&state, time, &stop);
+                if !stop() && answer_sender.send((id, answer)).is_err() {
+                    break;
+                }
+            }
+        });
+        BackgroundEngine {positions, answers, generation, position: None, name}
+    }
+
+    // Starts thinking about the position, unless it already does
+    pub fn ask(&mut self, state: &ChessState) {
+        if self.position.as_ref() == Some(state) {
+            return;
+        }
+        let id = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        self.position = Some(state.clone());
+        let _ = self.positions.send((id, state.clone()));
+    }
+
+    // The position being thought about
+    pub fn position(&self) -> Option<&ChessState> {
+        self.position.as_ref()
+    }
+
+    // Gives up thinking about the position
+    pub fn cancel(&mut self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.position = None;
+    }
+
+    // The engine's move for the position once it has found it
+    pub fn answer(&mut self) -> Option<io::Result<Option<Move>>> {
+        let current = self.generation.load(Ordering::Relaxed);
+        while let Ok((id, answer)) = self.answers.try_recv() {
+            if id == current && self.position.is_some() {
+                self.position = None;
+                return Some(answer);
+            }
+        }
+        None
+    }
+}
+
+impl Drop for BackgroundEngine {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    // Answers every search with the first move of the Ruy Lopez, if legal
+    const FAKE_ENGINE: &str = "while read line; do case \"$line\" in \
+                               uci) echo 'id name Fake 1.0'; echo uciok;; \
+                               isready) echo readyok;; \
+                               go*) echo 'info depth 1'; echo 'bestmove f1b5';; \
+                               quit) exit;; esac; done";
+
+    #[test]
+    fn asks_for_moves() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(FAKE_ENGINE);
+        let mut engine = ExternalEngine::start(command).unwrap();
+        assert_eq!(engine.name, "Fake 1.0");
+        let mut state = ChessState::new();
+        for mv in ["e4", "e5", "Nf3", "Nc6"] {
+            let mv = notation::parse_san(&state, mv).unwrap();
+            state.make_move(mv);
+        }
+        let mv = engine.best_move(&state, Duration::from_millis(10)).unwrap();
+        assert_eq!(mv.unwrap().to_string(), "f1b5");
+        assert!(engine.best_move(&ChessState::new(), Duration::from_millis(10)).is_err());
+    }
+
+    #[test]
+    fn gives_up_on_silent_engines() {
+        // not an engine at all
+        let mut command = Command::new("cat");
+        let started = Instant::now();
+        let e = ExternalEngine::start_within(command, Duration::from_millis(100)).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // an engine that never finds a move
+        command = Command::new("sh");
+        command.arg("-c").arg(FAKE_ENGINE.replace("echo 'bestmove f1b5'", "true"));
+        let mut engine = ExternalEngine::start(command).unwrap();
+        let e = engine.best_move(&ChessState::new(), Duration::from_millis(10)).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn thinks_in_the_background() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(FAKE_ENGINE);
+        let mut engine = BackgroundEngine::new(ExternalEngine::start(command).unwrap(), Duration::from_millis(10));
+        let mut state = ChessState::new();
+        for mv in ["e4", "e5", "Nf3", "Nc6"] {
+            let mv = notation::parse_san(&state, mv).unwrap();
+            state.make_move(mv);
+        }
+        // the search for the start position is given up
+        engine.ask(&ChessState::new());
+        engine.ask(&state);
+        assert_eq!(engine.position(), Some(&state));
+        let started = Instant::now();
+        let answer = loop {
+            if let Some(answer) = engine.answer() {
+                break answer;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        };
+        assert_eq!(answer.unwrap().unwrap().to_string(), "f1b5");
+        assert_eq!(engine.position(), None);
+    }
+}
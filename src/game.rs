@@ -0,0 +1,400 @@
+use crate::clock::Clock;
+use crate::state::{ChessState, Color, Figure, Move, MoveError, Pos};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Who makes the moves for one side
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Player {
+    Human,
+    // the built-in engine, searching to the given depth
+    Engine { depth: u32 },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum WinReason {
+    Checkmate,
+    Timeout,
+    Resignation,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DrawReason {
+    Stalemate,
+    // neither player can mate anymore
+    InsufficientMaterial,
+    // a flag fell, but the opponent could not have mated
+    TimeoutVsInsufficientMaterial,
+    Agreement,
+    // claimed by a player
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    // these end the game without a claim
+    FivefoldRepetition,
+    SeventyFiveMoveRule,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Outcome {
+    Win(Color, WinReason),
+    Draw(DrawReason),
+}
+
+// Describes the outcome, e.g. "White wins by checkmate"
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Win(color, reason) => {
+                let reason = match reason {
+                    WinReason::Checkmate => "checkmate",
+                    WinReason::Timeout => "timeout",
+                    WinReason::Resignation => "resignation",
+                };
+                let color = match color {
+                    Color::White => "White",
+                    Color::Black => "Black",
+                };
+                write!(f, "{} wins by {}", color, reason)
+            },
+            Outcome::Draw(reason) => {
+                let reason = match reason {
+                    DrawReason::Stalemate => "stalemate",
+                    DrawReason::InsufficientMaterial => "insufficient material",
+                    DrawReason::TimeoutVsInsufficientMaterial => "timeout vs insufficient material",
+                    DrawReason::Agreement => "agreement",
+                    DrawReason::ThreefoldRepetition => "threefold repetition",
+                    DrawReason::FiftyMoveRule => "the fifty-move rule",
+                    DrawReason::FivefoldRepetition => "fivefold repetition",
+                    DrawReason::SeventyFiveMoveRule => "the seventy-five-move rule",
+                };
+                write!(f, "Draw by {}", reason)
+            },
+        }
+    }
+}
+
+// A game being played: the position with its history, who plays which side
+// and the clocks
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(from = "GameFields"))]
+pub struct Game {
+    // identifies the game, e.g. the name of its save file
+    pub id: String,
+    // changed through the methods of Game, which keep the outcome up to date
+    pub state: ChessState,
+    pub white: Player,
+    pub black: Player,
+    // None for games without time control
+    pub clock: Option<Clock>,
+    // outcomes that do not follow from the position alone, like timeouts
+    result: Option<Outcome>,
+    // the player who offered a draw, until the opponent accepts or moves
+    draw_offer: Option<Color>,
+    // the number of hints White and Black asked for
+    #[cfg_attr(feature = "serde", serde(default))]
+    hints: [u32; 2],
+    // the outcome that follows from the position, kept up to date by the
+    // methods changing it as finding it takes a while
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    ended: Option<Outcome>,
+}
+
+// The serialized form of a Game, the outcome of the position is found again
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct GameFields {
+    id: String,
+    state: ChessState,
+    white: Player,
+    black: Player,
+    clock: Option<Clock>,
+    result: Option<Outcome>,
+    draw_offer: Option<Color>,
+    #[serde(default)]
+    hints: [u32; 2],
+}
+
+#[cfg(feature = "serde")]
+impl From<GameFields> for Game {
+    fn from(fields: GameFields) -> Self {
+        let mut game = Game {id: fields.id,
+                             state: fields.state,
+                             white: fields.white,
+                             black: fields.black,
+                             clock: fields.clock,
+                             result: fields.result,
+                             draw_offer: fields.draw_offer,
+                             hints: fields.hints,
+                             ended: None};
+        game.update_outcome();
+        game
+    }
+}
+
+// The outcome that follows from the rules alone
+fn position_outcome(state: &ChessState) -> Option<Outcome> {
+    if !state.player_has_moves() {
+        return Some(if state.player_is_in_check() {
+            Outcome::Win(state.turn.other(), WinReason::Checkmate)
+        } else {
+            Outcome::Draw(DrawReason::Stalemate)
+        });
+    }
+    if !state.has_mating_material(Color::White) && !state.has_mating_material(Color::Black) {
+        return Some(Outcome::Draw(DrawReason::InsufficientMaterial));
+    }
+    if state.halfmove_clock >= 150 {
+        return Some(Outcome::Draw(DrawReason::SeventyFiveMoveRule));
+    }
+    if state.halfmove_clock >= 8 && state.repetitions() >= 5 {
+        return Some(Outcome::Draw(DrawReason::FivefoldRepetition));
+    }
+    None
+}
+
+impl Game {
+    pub fn new(state: ChessState, white: Player, black: Player) -> Self {
+        // the start time is unique enough to tell games apart
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let mut game = Game {id: started.to_string(),
+                             state,
+                             white,
+                             black,
+                             clock: None,
+                             result: None,
+                             draw_offer: None,
+                             hints: [0, 0],
+                             ended: None};
+        game.update_outcome();
+        game
+    }
+
+    pub fn player(&self, color: Color) -> Player {
+        match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        }
+    }
+
+    // The player whose turn it is
+    pub fn current_player(&self) -> Player {
+        self.player(self.state.turn)
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.result.or(self.ended)
+    }
+
+    // Finds the outcome of the position after it changed
+    fn update_outcome(&mut self) {
+        self.ended = position_outcome(&self.state);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.outcome().is_some()
+    }
+
+    pub fn move_checked(&mut self, pos: Pos, new_pos: Pos) -> Result<(), MoveError> {
+        self.promote_checked(pos, new_pos, Figure::Queen(self.state.turn, true))
+    }
+
+    pub fn promote_checked(&mut self, pos: Pos, new_pos: Pos, promotion: Figure) -> Result<(), MoveError> {
+        if self.is_finished() {
+            return Err(MoveError::GameOver);
+        }
+        let color = self.state.turn;
+        self.state.promote_checked(pos, new_pos, promotion)?;
+        self.after_move(color);
+        Ok(())
+    }
+
+    // Plays a move without checking it, it has to come from legal_moves
+    pub fn make_move(&mut self, mv: Move) {
+        let color = self.state.turn;
+        self.state.make_move(mv);
+        self.after_move(color);
+    }
+
+    fn after_move(&mut self, color: Color) {
+        self.update_outcome();
+        if let Some(clock) = &mut self.clock {
+            clock.press(color);
+        }
+        // moving instead of accepting declines the opponents offer
+        if self.draw_offer == Some(color.other()) {
+            self.draw_offer = None;
+        }
+    }
+
+    // Takes back the last move, which also undoes a result like resigning.
+    // The clocks keep running as they are.
+    pub fn undo(&mut self) -> Result<Move, ()> {
+        let mv = self.state.unmake_move().ok_or(())?;
+        self.update_outcome();
+        self.result = None;
+        self.draw_offer = None;
+        Ok(mv)
+    }
+
+    pub fn resign(&mut self, color: Color) -> Result<(), ()> {
+        if self.is_finished() {
+            return Err(());
+        }
+        self.result = Some(Outcome::Win(color.other(), WinReason::Resignation));
+        Ok(())
+    }
+
+    // Records an outcome that does not follow from the position, e.g. one
+    // read from a PGN file
+    pub(crate) fn set_result(&mut self, outcome: Outcome) {
+        self.result = Some(outcome);
+    }
+
+    pub fn hints(&self, color: Color) -> u32 {
+        self.hints[color.index()]
+    }
+
+    pub fn count_hint(&mut self, color: Color) {
+        self.hints[color.index()] += 1;
+    }
+
+    pub fn draw_offer(&self) -> Option<Color> {
+        self.draw_offer
+    }
+
+    pub fn offer_draw(&mut self, color: Color) -> Result<(), ()> {
+        if self.is_finished() || self.draw_offer.is_some() {
+            return Err(());
+        }
+        self.draw_offer = Some(color);
+        Ok(())
+    }
+
+    pub fn accept_draw(&mut self, color: Color) -> Result<(), ()> {
+        if self.is_finished() || self.draw_offer != Some(color.other()) {
+            return Err(());
+        }
+        self.draw_offer = None;
+        self.result = Some(Outcome::Draw(DrawReason::Agreement));
+        Ok(())
+    }
+
+    // Ends the game in a draw if the position has occurred three times or
+    // there were fifty moves without a capture or Pawn move
+    pub fn claim_draw(&mut self) -> Result<DrawReason, ()> {
+        if self.is_finished() {
+            return Err(());
+        }
+        let reason = if self.state.halfmove_clock >= 100 {
+            DrawReason::FiftyMoveRule
+        } else if self.state.repetitions() >= 3 {
+            DrawReason::ThreefoldRepetition
+        } else {
+            return Err(());
+        };
+        self.result = Some(Outcome::Draw(reason));
+        Ok(reason)
+    }
+
+    // Lets time pass on the clock of the player to move
+    pub fn tick(&mut self, elapsed: Duration) {
+        if self.is_finished() {
+            return;
+        }
+        let turn = self.state.turn;
+        let flag_fell = match &mut self.clock {
+            Some(clock) => clock.tick(turn, elapsed),
+            None => false,
+        };
+        if flag_fell {
+            self.result = Some(if self.state.has_mating_material(turn.other()) {
+                Outcome::Win(turn.other(), WinReason::Timeout)
+            } else {
+                Outcome::Draw(DrawReason::TimeoutVsInsufficientMaterial)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TimeControl;
+
+    #[test]
+    fn timeout() {
+        let state = ChessState::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1").unwrap();
+        let mut game = Game::new(state, Player::Human, Player::Human);
+        game.clock = Some(Clock::new(TimeControl::sudden_death(Duration::from_secs(60))));
+        game.tick(Duration::from_secs(59));
+        assert_eq!(game.outcome(), None);
+        game.tick(Duration::from_secs(1));
+        assert_eq!(game.outcome(), Some(Outcome::Win(Color::White, WinReason::Timeout)));
+    }
+
+    #[test]
+    fn timeout_vs_insufficient_material() {
+        let state = ChessState::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        let mut game = Game::new(state, Player::Human, Player::Human);
+        game.clock = Some(Clock::new(TimeControl::sudden_death(Duration::from_secs(60))));
+        game.tick(Duration::from_secs(60));
+        assert_eq!(game.outcome(), Some(Outcome::Draw(DrawReason::TimeoutVsInsufficientMaterial)));
+    }
+
+    #[test]
+    fn draw_offers() {
+        let mut game = Game::new(ChessState::new(), Player::Human, Player::Human);
+        game.offer_draw(Color::White).unwrap();
+        assert!(game.accept_draw(Color::White).is_err());
+        game.move_checked("e2".parse().unwrap(), "e4".parse().unwrap()).unwrap();
+        // Black moves instead of accepting
+        game.move_checked("e7".parse().unwrap(), "e5".parse().unwrap()).unwrap();
+        assert_eq!(game.draw_offer(), None);
+        game.offer_draw(Color::White).unwrap();
+        game.accept_draw(Color::Black).unwrap();
+        assert_eq!(game.outcome(), Some(Outcome::Draw(DrawReason::Agreement)));
+    }
+
+    #[test]
+    fn claim_threefold() {
+        let mut game = Game::new(ChessState::new(), Player::Human, Player::Human);
+        assert!(game.claim_draw().is_err());
+        for _ in 0..2 {
+            for (from, to) in [("g1", "f3"), ("g8", "f6"), ("f3", "g1"), ("f6", "g8")] {
+                game.move_checked(from.parse().unwrap(), to.parse().unwrap()).unwrap();
+            }
+        }
+        assert_eq!(game.claim_draw(), Ok(DrawReason::ThreefoldRepetition));
+        assert!(game.resign(Color::White).is_err());
+    }
+
+    #[test]
+    fn fivefold_repetition() {
+        let mut game = Game::new(ChessState::new(), Player::Human, Player::Human);
+        for _ in 0..4 {
+            for (from, to) in [("g1", "f3"), ("g8", "f6"), ("f3", "g1"), ("f6", "g8")] {
+                game.move_checked(from.parse().unwrap(), to.parse().unwrap()).unwrap();
+            }
+        }
+        assert_eq!(game.outcome(), Some(Outcome::Draw(DrawReason::FivefoldRepetition)));
+        game.undo().unwrap();
+        assert_eq!(game.outcome(), None);
+        game.move_checked("f6".parse().unwrap(), "g8".parse().unwrap()).unwrap();
+        // the outcome is found again for a loaded game
+        #[cfg(feature = "serde")]
+        {
+            let loaded: Game = serde_json::from_str(&serde_json::to_string(&game).unwrap()).unwrap();
+            assert_eq!(loaded.outcome(), Some(Outcome::Draw(DrawReason::FivefoldRepetition)));
+            assert_eq!(loaded, game);
+        }
+    }
+}
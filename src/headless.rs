@@ -0,0 +1,142 @@
+use crate::game::{Game, Outcome, Player};
+use crate::notation;
+use crate::pgn;
+use crate::state::{ChessState, Color, Figure, Move};
+use std::io::{self, BufRead, Write};
+
+// How the position is printed after every move
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum BoardFormat {
+    Text,
+    Fen,
+}
+
+// The exit status for scripts: 0 while the game is still going on, then
+// 10 if White won, 20 if Black won and 30 for draws
+pub fn exit_code(outcome: Option<Outcome>) -> i32 {
+    match outcome {
+        None => 0,
+        Some(Outcome::Win(Color::White, _)) => 10,
+        Some(Outcome::Win(Color::Black, _)) => 20,
+        Some(Outcome::Draw(_)) => 30,
+    }
+}
+
+fn print_move(game: &Game, output: &mut impl Write, format: BoardFormat) -> io::Result<()> {
+    let mut before = game.state.clone();
+    let mv = match before.unmake_move() {
+        Some(mv) => mv,
+        None => return Ok(()),
+    };
+    writeln!(output, "{}", notation::san_line(&before, &[mv]))?;
+    match format {
+        BoardFormat::Text => write!(output, "{}", game.state.board)?,
+        BoardFormat::Fen => writeln!(output, "{}", game.state.to_fen())?,
+    }
+    Ok(())
+}
+
+// Lets the engine players move until it is a human's turn
+fn engine_moves(game: &mut Game,
+                engine_move: &mut impl FnMut(&ChessState, u32) -> Option<Move>,
+                output: &mut impl Write,
+                format: BoardFormat) -> io::Result<()> {
+    while let Player::Engine {depth} = game.current_player() {
+        if game.is_finished() {
+            break;
+        }
+        match engine_move(&game.state, depth) {
+            Some(mv) => game.make_move(mv),
+            None => break,
+        }
+        print_move(game, output, format)?;
+    }
+    Ok(())
+}
+
+// Plays the game without a terminal user interface. Moves are read line by
+// line in SAN or UCI notation, "resign" resigns for the player to move. Each
+// move is printed with the position after it, rejected input is reported on
+// errors. Reading stops at the end of the game, whose result is printed last.
+// The moves of engine players are found by engine_move from the position and
+// the depth they search.
+pub fn run(game: &mut Game,
+           input: impl BufRead,
+           mut output: impl Write,
+           mut errors: impl Write,
+           format: BoardFormat,
+           mut engine_move: impl FnMut(&ChessState, u32) -> Option<Move>) -> io::Result<()> {
+    engine_moves(game, &mut engine_move, &mut output, format)?;
+    for line in input.lines() {
+        if game.is_finished() {
+            break;
+        }
+        let line = line?;
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if text == "resign" {
+            let _ = game.resign(game.state.turn);
+            break;
+        }
+        if game.current_player() != Player::Human {
+            writeln!(errors, "error: it is not your turn")?;
+            continue;
+        }
+        let (from, to, promotion) = match notation::parse_input(&game.state, text) {
+            Ok(parsed) => parsed,
+            Err(()) => {
+                writeln!(errors, "error: illegal move '{}'", text)?;
+                continue;
+            },
+        };
+        let promotion = promotion.unwrap_or(Figure::Queen(game.state.turn, true));
+        if let Err(e) = game.promote_checked(from, to, promotion) {
+            writeln!(errors, "error: illegal move '{}': {}", text, e)?;
+            continue;
+        }
+        print_move(game, &mut output, format)?;
+        engine_moves(game, &mut engine_move, &mut output, format)?;
+    }
+    match game.outcome() {
+        Some(outcome) => writeln!(output, "{} {}", pgn::result_tag(Some(outcome)), outcome)?,
+        None => writeln!(output, "*")?,
+    }
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(fen: &str, input: &str, format: BoardFormat) -> (Game, String, String) {
+        let mut game = Game::new(ChessState::from_fen(fen).unwrap(), Player::Human, Player::Human);
+        let mut output = vec![];
+        let mut errors = vec![];
+        run(&mut game, input.as_bytes(), &mut output, &mut errors, format, |_, _| None).unwrap();
+        (game, String::from_utf8(output).unwrap(), String::from_utf8(errors).unwrap())
+    }
+
+    #[test]
+    fn fools_mate() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let (game, output, errors) = session(start, "f3\ne7e5\ng5\ne7e6\ng4\nQh4#\n", BoardFormat::Fen);
+        assert_eq!(errors, "error: illegal move 'g5'\nerror: illegal move 'e7e6': there is no figure on that square\n");
+        assert!(output.starts_with("1. f3\nrnbqkbnr/pppppppp/8/8/8/5P2/PPPPP1PP/RNBQKBNR b KQkq - 0 1\n1... e5\n"));
+        assert!(output.ends_with("2... Qh4#\nrnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3\n\
+                                  0-1 Black wins by checkmate\n"));
+        assert_eq!(exit_code(game.outcome()), 20);
+    }
+
+    #[test]
+    fn underpromotion() {
+        let (_, output, errors) = session("8/p3P1k1/8/8/8/8/8/4K3 w - - 0 1", "e7e8n\nxyz\n", BoardFormat::Text);
+        assert_eq!(errors, "error: illegal move 'xyz'\n");
+        assert!(output.starts_with("1. e8=N+\n8  . . . . N . . .\n7  p . . . . . k .\n"));
+        assert!(output.ends_with("   a b c d e f g h\n*\n"));
+        // promotion letters only for Pawns reaching the last rank
+        let (_, _, errors) = session("4k3/p3P3/8/8/8/8/4P3/4K3 w - - 0 1", "e2e4q\ne7e8k\ne1d1r\n", BoardFormat::Fen);
+        assert_eq!(errors, "error: illegal move 'e2e4q'\nerror: illegal move 'e7e8k'\nerror: illegal move 'e1d1r'\n");
+    }
+}
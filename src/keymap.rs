@@ -0,0 +1,458 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fmt;
+use std::str::FromStr;
+
+// Everything the user can do with a key in the terminal user interface
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+    Select,
+    Cancel,
+    Quit,
+    Resign,
+    Confirm,
+    Draw,
+    ClaimDraw,
+    Help,
+    // opens the prompt for moves and commands
+    Command,
+    // shows the engine's evaluation beside the board
+    Analyze,
+    // asks the engine for a move
+    Hint,
+    // browsing the moves played so far
+    GameStart,
+    MoveBack,
+    MoveForward,
+    GameEnd,
+}
+
+impl Action {
+    pub const ALL: [Action; 23] = [Action::Up, Action::Down, Action::Left, Action::Right,
+                                   Action::UpLeft, Action::UpRight, Action::DownLeft, Action::DownRight,
+                                   Action::Select, Action::Cancel, Action::Quit, Action::Resign,
+                                   Action::Confirm, Action::Draw, Action::ClaimDraw, Action::Help, Action::Command, Action::Analyze,
+                                   Action::Hint,
+                                   Action::GameStart, Action::MoveBack, Action::MoveForward, Action::GameEnd];
+
+    // The name used in the config file
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::UpLeft => "up_left",
+            Action::UpRight => "up_right",
+            Action::DownLeft => "down_left",
+            Action::DownRight => "down_right",
+            Action::Select => "select",
+            Action::Cancel => "cancel",
+            Action::Quit => "quit",
+            Action::Resign => "resign",
+            Action::Confirm => "confirm",
+            Action::Draw => "draw",
+            Action::ClaimDraw => "claim_draw",
+            Action::Help => "help",
+            Action::Command => "command",
+            Action::Analyze => "analyze",
+            Action::Hint => "hint",
+            Action::GameStart => "game_start",
+            Action::MoveBack => "move_back",
+            Action::MoveForward => "move_forward",
+            Action::GameEnd => "game_end",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Up => "cursor up",
+            Action::Down => "cursor down",
+            Action::Left => "cursor left",
+            Action::Right => "cursor right",
+            Action::UpLeft => "cursor up and left",
+            Action::UpRight => "cursor up and right",
+            Action::DownLeft => "cursor down and left",
+            Action::DownRight => "cursor down and right",
+            Action::Select => "select a figure, or move it",
+            Action::Cancel => "cancel the selection",
+            Action::Quit => "save and quit",
+            Action::Resign => "resign",
+            Action::Confirm => "confirm resigning",
+            Action::Draw => "offer or accept a draw",
+            Action::ClaimDraw => "claim a draw",
+            Action::Help => "show or hide this help",
+            Action::Command => "type a move or a command",
+            Action::Analyze => "show or hide the engine analysis",
+            Action::Hint => "ask the engine for a move",
+            Action::GameStart => "go to the start of the game",
+            Action::MoveBack => "go one move back",
+            Action::MoveForward => "go one move forward",
+            Action::GameEnd => "go back to the current position",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Action::ALL.into_iter().find(|action| action.name() == s).ok_or(())
+    }
+}
+
+// A key with its modifiers
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode) -> Self {
+        Key {code, modifiers: KeyModifiers::NONE}
+    }
+
+    pub fn from_event(event: KeyEvent) -> Self {
+        // Shift is already part of upper case letters and symbols
+        let mut modifiers = event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        if let KeyCode::Char(_) = event.code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Key {code: event.code, modifiers}
+    }
+}
+
+const KEY_NAMES: [(&str, KeyCode); 13] = [
+    ("space", KeyCode::Char(' ')),
+    ("esc", KeyCode::Esc),
+    ("enter", KeyCode::Enter),
+    ("tab", KeyCode::Tab),
+    ("backspace", KeyCode::Backspace),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+];
+
+// Keys are written as a single character, a name like "space", "esc",
+// "up" or "f1", with "ctrl-" or "alt-" in front for modifiers
+impl FromStr for Key {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s;
+        loop {
+            if let Some(r) = rest.strip_prefix("ctrl-").filter(|r| !r.is_empty()) {
+                modifiers |= KeyModifiers::CONTROL;
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("alt-").filter(|r| !r.is_empty()) {
+                modifiers |= KeyModifiers::ALT;
+                rest = r;
+            } else {
+                break;
+            }
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => {
+                let name = rest.to_ascii_lowercase();
+                match KEY_NAMES.iter().find(|(n, _)| *n == name) {
+                    Some((_, code)) => *code,
+                    None => KeyCode::F(name.strip_prefix('f').and_then(|n| n.parse().ok()).ok_or(())?),
+                }
+            },
+        };
+        Ok(Key {code, modifiers})
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt-")?;
+        }
+        match self.code {
+            KeyCode::F(n) => write!(f, "f{}", n),
+            KeyCode::Char(c) if c != ' ' => write!(f, "{}", c),
+            code => match KEY_NAMES.iter().find(|(_, c)| *c == code) {
+                Some((name, _)) => write!(f, "{}", name),
+                None => write!(f, "?"),
+            },
+        }
+    }
+}
+
+// A sequence of keys like "g g", separated by spaces
+pub fn parse_sequence(s: &str) -> Result<Vec<Key>, ()> {
+    let keys = s.split_whitespace().map(str::parse).collect::<Result<Vec<Key>, ()>>()?;
+    if keys.is_empty() {
+        return Err(());
+    }
+    Ok(keys)
+}
+
+fn format_sequence(keys: &[Key]) -> String {
+    keys.iter().map(ToString::to_string).collect::<Vec<_>>().join(" ")
+}
+
+// Maps key sequences to actions. Keys are fed in one by one, so that
+// sequences like "g g" can be recognised.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+    // the keys of a sequence typed so far
+    pending: Vec<Key>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::vim()
+    }
+}
+
+impl Keymap {
+    pub const PRESETS: [&'static str; 4] = ["vim", "arrows", "wasd", "numpad"];
+
+    fn from_strs(bindings: &[(&str, Action)]) -> Self {
+        let bindings = bindings
+            .iter()
+            .map(|(keys, action)| (parse_sequence(keys).unwrap(), *action))
+            .collect();
+        Keymap {bindings, pending: vec![]}
+    }
+
+    // Bindings shared by all presets, draw offers are bound by the presets
+    fn common(&mut self) {
+        let common = Self::from_strs(&[("esc", Action::Cancel),
+                                       ("q", Action::Quit),
+                                       ("r", Action::Resign),
+                                       ("y", Action::Confirm),
+                                       ("c", Action::ClaimDraw),
+                                       ("?", Action::Help),
+                                       (":", Action::Command),
+                                       ("/", Action::Command),
+                                       ("e", Action::Analyze),
+                                       ("t", Action::Hint),
+                                       ("g g", Action::GameStart),
+                                       ("[", Action::MoveBack),
+                                       ("]", Action::MoveForward),
+                                       ("G", Action::GameEnd)]);
+        self.bindings.extend(common.bindings);
+    }
+
+    pub fn vim() -> Self {
+        let mut keymap = Self::from_strs(&[("h", Action::Left),
+                                           ("j", Action::Down),
+                                           ("k", Action::Up),
+                                           ("l", Action::Right),
+                                           ("space", Action::Select),
+                                           ("d", Action::Draw)]);
+        keymap.common();
+        keymap
+    }
+
+    pub fn arrows() -> Self {
+        let mut keymap = Self::from_strs(&[("left", Action::Left),
+                                           ("down", Action::Down),
+                                           ("up", Action::Up),
+                                           ("right", Action::Right),
+                                           ("enter", Action::Select),
+                                           ("space", Action::Select),
+                                           ("d", Action::Draw),
+                                           ("home", Action::GameStart),
+                                           ("end", Action::GameEnd)]);
+        keymap.common();
+        keymap
+    }
+
+    pub fn wasd() -> Self {
+        let mut keymap = Self::from_strs(&[("a", Action::Left),
+                                           ("s", Action::Down),
+                                           ("w", Action::Up),
+                                           ("d", Action::Right),
+                                           ("space", Action::Select),
+                                           ("o", Action::Draw)]);
+        keymap.common();
+        keymap
+    }
+
+    // The digits as laid out on a numeric keypad
+    pub fn numpad() -> Self {
+        let mut keymap = Self::from_strs(&[("4", Action::Left),
+                                           ("2", Action::Down),
+                                           ("8", Action::Up),
+                                           ("6", Action::Right),
+                                           ("7", Action::UpLeft),
+                                           ("9", Action::UpRight),
+                                           ("1", Action::DownLeft),
+                                           ("3", Action::DownRight),
+                                           ("5", Action::Select),
+                                           ("enter", Action::Select),
+                                           ("0", Action::Cancel),
+                                           ("d", Action::Draw)]);
+        keymap.common();
+        keymap
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "vim" => Some(Self::vim()),
+            "arrows" => Some(Self::arrows()),
+            "wasd" => Some(Self::wasd()),
+            "numpad" => Some(Self::numpad()),
+            _ => None,
+        }
+    }
+
+    // Replaces all bindings of the action. Other actions lose the same
+    // sequences and those that start with one of them or one of them starts
+    // with, as press would find those first.
+    pub fn bind(&mut self, action: Action, sequences: Vec<Vec<Key>>) {
+        self.bindings.retain(|(keys, a)| {
+            *a != action && !sequences.iter().any(|new| keys.starts_with(new) || new.starts_with(keys))
+        });
+        self.bindings.extend(sequences.into_iter().map(|keys| (keys, action)));
+    }
+
+    // Feeds in a key press, returns the action once a whole sequence has
+    // been typed
+    pub fn press(&mut self, key: Key) -> Option<Action> {
+        self.pending.push(key);
+        loop {
+            if let Some((_, action)) = self.bindings.iter().find(|(keys, _)| *keys == self.pending) {
+                self.pending.clear();
+                return Some(*action);
+            }
+            if self.bindings.iter().any(|(keys, _)| keys.starts_with(&self.pending)) {
+                return None;
+            }
+            // no sequence goes on like this, maybe one starts with the key
+            if self.pending.len() == 1 {
+                self.pending.clear();
+                return None;
+            }
+            self.pending = vec![key];
+        }
+    }
+
+    // The keys bound to the action, e.g. "k, up"
+    pub fn keys(&self, action: Action) -> String {
+        self.bindings
+            .iter()
+            .filter(|(_, a)| *a == action)
+            .map(|(keys, _)| format_sequence(keys))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // All bound actions with their keys, for the help overlay
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        Action::ALL
+            .iter()
+            .map(|action| (self.keys(*action), action.description()))
+            .filter(|(keys, _)| !keys.is_empty())
+            .collect()
+    }
+
+    // A short reminder of the most important keys for the status bar
+    pub fn hints(&self, finished: bool) -> String {
+        let first = |action| self.keys(action).split(", ").next().unwrap_or("").to_owned();
+        let mut hints = vec![];
+        if !finished {
+            let moves: Vec<String> = [Action::Left, Action::Down, Action::Up, Action::Right]
+                .into_iter()
+                .map(first)
+                .collect();
+            let moves = if moves.iter().all(|keys| keys.chars().count() == 1) {
+                moves.concat()
+            } else {
+                moves.join("/")
+            };
+            hints.push(format!("{} move", moves));
+            hints.push(format!("{} select", first(Action::Select)));
+        }
+        hints.push(format!("{} command", first(Action::Command)));
+        hints.push(format!("{} help", first(Action::Help)));
+        hints.push(format!("{} quit", first(Action::Quit)));
+        hints.join("  ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(s: &str) -> Key {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_keys() {
+        for s in ["k", "G", "?", "space", "esc", "up", "f5", "ctrl-c", "alt-enter"] {
+            assert_eq!(key(s).to_string(), s);
+        }
+        assert_eq!(key("Space"), Key::new(KeyCode::Char(' ')));
+        assert_eq!(key("ctrl--").code, KeyCode::Char('-'));
+        assert!("ctrl-".parse::<Key>().is_err());
+        assert!("foo".parse::<Key>().is_err());
+        assert!(parse_sequence(" ").is_err());
+    }
+
+    #[test]
+    fn sequences() {
+        let mut keymap = Keymap::vim();
+        assert_eq!(keymap.press(key("g")), None);
+        assert_eq!(keymap.press(key("g")), Some(Action::GameStart));
+        // a key that does not continue the sequence starts a new one
+        assert_eq!(keymap.press(key("g")), None);
+        assert_eq!(keymap.press(key("k")), Some(Action::Up));
+        assert_eq!(keymap.press(key("x")), None);
+        assert_eq!(keymap.press(key("G")), Some(Action::GameEnd));
+    }
+
+    #[test]
+    fn rebinding() {
+        let mut keymap = Keymap::wasd();
+        assert_eq!(keymap.press(key("d")), Some(Action::Right));
+        keymap.bind(Action::Up, vec![vec![key("i")], vec![key("up")]]);
+        assert_eq!(keymap.press(key("w")), None);
+        assert_eq!(keymap.press(key("up")), Some(Action::Up));
+        assert_eq!(keymap.keys(Action::Up), "i, up");
+        assert_eq!(keymap.hints(false), "asid move  space select  : command  ? help  q quit");
+        assert_eq!(Keymap::arrows().hints(true), ": command  ? help  q quit");
+    }
+
+    #[test]
+    fn rebinding_takes_keys() {
+        let mut keymap = Keymap::wasd();
+        keymap.bind(Action::Draw, vec![vec![key("d")]]);
+        assert_eq!(keymap.press(key("d")), Some(Action::Draw));
+        assert_eq!(keymap.keys(Action::Draw), "d");
+        assert_eq!(keymap.keys(Action::Right), "");
+        // a sequence starting with the key, and a key starting the sequence
+        keymap.bind(Action::Help, vec![vec![key("g")]]);
+        assert_eq!(keymap.keys(Action::GameStart), "");
+        assert_eq!(keymap.press(key("g")), Some(Action::Help));
+        keymap.bind(Action::Quit, vec![vec![key("a"), key("b")]]);
+        assert_eq!(keymap.keys(Action::Left), "");
+        assert_eq!(keymap.press(key("a")), None);
+        assert_eq!(keymap.press(key("b")), Some(Action::Quit));
+    }
+}
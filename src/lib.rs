@@ -0,0 +1,43 @@
+#![allow(clippy::result_unit_err)]
+
+#[cfg(feature = "engine")]
+pub mod analysis;
+pub mod bitboard;
+pub mod book;
+pub mod clock;
+#[cfg(all(feature = "tui", feature = "serde"))]
+pub mod config;
+#[cfg(feature = "engine")]
+pub mod engine;
+#[cfg(feature = "engine")]
+pub mod eval;
+pub mod external;
+pub mod game;
+pub mod headless;
+#[cfg(feature = "tui")]
+pub mod keymap;
+pub mod movelist;
+pub mod notation;
+pub mod perft;
+pub mod pgn;
+#[cfg(feature = "tui")]
+pub mod prompt;
+#[cfg(feature = "tui")]
+pub mod render;
+#[cfg(feature = "engine")]
+pub mod review;
+#[cfg(feature = "serde")]
+pub mod save;
+pub mod state;
+pub mod tutorial;
+#[cfg(feature = "engine")]
+pub mod tune;
+#[cfg(feature = "engine")]
+pub mod uci;
+
+pub use crate::clock::{Clock, TimeControl};
+pub use crate::game::{DrawReason, Game, Outcome, Player, WinReason};
+pub use crate::movelist::{MoveFilter, MoveList};
+#[cfg(feature = "tui")]
+pub use crate::render::{ChessWidget, ChessWidgetState, ColorDepth, PieceSet, Theme};
+pub use crate::state::{Board, ChessState, Color, Dir, Figure, Move, MoveError, Pos};
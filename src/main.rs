@@ -1,54 +1,404 @@
-use crate::render::{ChessWidget, ChessWidgetState};
-use crate::state::Dir;
+mod cli;
+
+use cli::{Cli, Command, DisplayArgs, Format, Output, PlayArgs, PositionArgs, Side};
+use clap::Parser;
+#[cfg(feature = "engine")]
+use tress::{analysis, engine, eval, notation, review, tune, uci};
+use tress::{config, headless, perft, pgn, save};
+use tress::book::Book;
+use tress::external::ExternalEngine;
+use tress::keymap::{Action, Key};
+use tress::{ChessState, ChessWidget, ChessWidgetState, Clock, Color, ColorDepth, Game, Player};
 use crossterm::{
-    event::{self, KeyCode, KeyEventKind},
+    event::{self, KeyEventKind},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
 use ratatui::{
     prelude::{CrosstermBackend, Terminal},
 };
-use std::io::{stderr, Result};
+use std::fs;
+use std::io::{stderr, Error, ErrorKind, Result};
+#[cfg(feature = "engine")]
+use std::sync::Arc;
+use std::time::Instant;
+
+fn list_games() -> Result<()> {
+    let games = save::unfinished_games()?;
+    if games.is_empty() {
+        println!("No unfinished games.");
+    }
+    for game in games {
+        let turn = match game.state.turn {
+            Color::White => "White",
+            Color::Black => "Black",
+        };
+        println!("{}  move {}, {} to move", game.id, game.state.fullmove_number, turn);
+    }
+    Ok(())
+}
 
-pub mod render;
-pub mod state;
+// Loads the game with the given id, or the most recent unfinished one
+fn resume(id: &str) -> Result<Game> {
+    if !id.is_empty() {
+        return save::load(id);
+    }
+    save::unfinished_games()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "no unfinished game to resume"))
+}
+
+// The game a command starts from, with both sides played by humans unless
+// a saved game says otherwise
+fn load_game(position: &PositionArgs) -> Result<Game> {
+    if let Some(id) = &position.resume {
+        return resume(id);
+    }
+    if let Some(path) = &position.pgn_in {
+        let text = fs::read_to_string(path)?;
+        return pgn::import(&text)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, format!("{}: invalid PGN", path.display())));
+    }
+    let state = match &position.fen {
+        Some(fen) => ChessState::from_fen(fen)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid FEN"))?,
+        None => ChessState::new(),
+    };
+    Ok(Game::new(state, Player::Human, Player::Human))
+}
+
+// The threads to search with, all CPU cores unless told otherwise
+fn threads(threads: Option<usize>) -> usize {
+    threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())).max(1)
+}
+
+#[cfg(feature = "engine")]
+fn analyze(position: &PositionArgs, depth: u32, threads: usize, weights: Option<&std::path::Path>) -> Result<()> {
+    let weights = load_weights(weights)?;
+    let game = load_game(position)?;
+    let mut state = game.state;
+    println!("{}", state.to_fen());
+    let searched = state.clone();
+    engine::search_threads(&mut state, depth, threads, weights, &|| false, |result| {
+        // from White's point of view, in pawns
+        let score = match searched.turn {
+            Color::White => result.score,
+            Color::Black => -result.score,
+        };
+        println!("depth {:2}  score {:>6}  nodes {:>9}  {}",
+                 result.depth, analysis::format_score(score), result.nodes, notation::san_line(&searched, &result.pv));
+    });
+    Ok(())
+}
+
+// The weights the engine evaluates with, from the file or the built-in ones
+#[cfg(feature = "engine")]
+fn load_weights(path: Option<&std::path::Path>) -> Result<Arc<eval::Weights>> {
+    match path {
+        Some(path) => Ok(Arc::new(eval::Weights::load(path)?)),
+        None => Ok(Arc::new(eval::Weights::default())),
+    }
+}
+
+// Prints the terms of the evaluation for both sides, in centipawns for the
+// middlegame and the endgame, and their total mixed by the game phase in
+// pawns from White's point of view
+#[cfg(feature = "engine")]
+fn eval_position(position: &PositionArgs, weights: Option<&std::path::Path>) -> Result<()> {
+    let weights = load_weights(weights)?;
+    let state = load_game(position)?.state;
+    let phase = eval::phase(&state);
+    println!("{}", state.to_fen());
+    println!("{:16} {:>13}  {:>13}  {:>6}", "", "White mg  eg", "Black mg  eg", "total");
+    let mut total = eval::Score::default();
+    for (term, [white, black]) in eval::breakdown(&state, &weights) {
+        let difference = white - black;
+        total += difference;
+        println!("{:16} {:>7} {:>5}  {:>7} {:>5}  {:>6}",
+                 term.name(), white.mg, white.eg, black.mg, black.eg,
+                 analysis::format_score(difference.taper(phase)));
+    }
+    println!("phase {}/{}, evaluation {}", phase, eval::MAX_PHASE, analysis::format_score(total.taper(phase)));
+    Ok(())
+}
+
+#[cfg(not(feature = "engine"))]
+fn eval_position(_position: &PositionArgs, _weights: Option<&std::path::Path>) -> Result<()> {
+    Err(no_engine())
+}
+
+#[cfg(feature = "engine")]
+fn tune_weights(data: &std::path::Path, out: &std::path::Path, weights: Option<&std::path::Path>,
+                iterations: u32, rate: f64) -> Result<()> {
+    let weights = match weights {
+        Some(path) => eval::Weights::load(path)?,
+        None => eval::Weights::default(),
+    };
+    let samples = tune::parse(&fs::read_to_string(data)?)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", data.display(), e)))?;
+    if samples.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{}: no positions", data.display())));
+    }
+    let k = tune::fit_k(&samples, &weights);
+    eprintln!("{} positions, K = {:.3}", samples.len(), k);
+    let tuned = tune::tune(&samples, &weights, k, iterations, rate, |iteration, error| {
+        match iteration {
+            i if i > iterations => eprint!("\rTuned, error {:.6}          ", error),
+            i => eprint!("\rIteration {}/{}, error {:.6}", i, iterations, error),
+        }
+    });
+    eprintln!();
+    fs::write(out, tuned.to_toml())
+}
+
+#[cfg(not(feature = "engine"))]
+fn tune_weights(_data: &std::path::Path, _out: &std::path::Path, _weights: Option<&std::path::Path>,
+                _iterations: u32, _rate: f64) -> Result<()> {
+    Err(no_engine())
+}
+
+#[cfg(feature = "engine")]
+fn review_game(position: &PositionArgs, depth: u32, pgn_out: Option<&std::path::Path>,
+               weights: Option<&std::path::Path>) -> Result<()> {
+    let weights = load_weights(weights)?;
+    let game = load_game(position)?;
+    let review = review::review(&game, depth, weights, |searched, total| eprint!("\rSearched {}/{} positions", searched, total));
+    eprintln!();
+    for mv in &review.moves {
+        let number = match mv.color {
+            Color::White => format!("{}.", mv.number),
+            Color::Black => format!("{}...", mv.number),
+        };
+        let best = match (mv.class.nag(), &mv.best_san) {
+            (Some(_), Some(best)) => format!("  {} was best", best),
+            _ => String::new(),
+        };
+        println!("{:>6} {:9} {:10} {:>6}{}",
+                 number, mv.san.clone() + mv.class.symbol(), mv.class, analysis::format_score(mv.score), best);
+    }
+    for color in [Color::White, Color::Black] {
+        let accuracy = review.accuracy(color).map_or("-".to_owned(), |accuracy| format!("{:.1}%", accuracy));
+        println!("{:5}  accuracy {:>6}  inaccuracies {}  mistakes {}  blunders {}",
+                 match color { Color::White => "White", Color::Black => "Black" },
+                 accuracy,
+                 review.count(color, review::Class::Inaccuracy),
+                 review.count(color, review::Class::Mistake),
+                 review.count(color, review::Class::Blunder));
+    }
+    if let Some(path) = pgn_out {
+        fs::write(path, review.export(&game))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "engine"))]
+fn review_game(_position: &PositionArgs, _depth: u32, _pgn_out: Option<&std::path::Path>,
+               _weights: Option<&std::path::Path>) -> Result<()> {
+    Err(no_engine())
+}
+
+#[cfg(not(feature = "engine"))]
+fn analyze(_position: &PositionArgs, _depth: u32, _threads: usize, _weights: Option<&std::path::Path>) -> Result<()> {
+    Err(no_engine())
+}
+
+#[cfg(not(feature = "engine"))]
+fn no_engine() -> Error {
+    Error::new(ErrorKind::Unsupported, "tress was built without the engine feature")
+}
+
+fn convert(position: &PositionArgs, to: Format, pgn_out: Option<&std::path::Path>) -> Result<()> {
+    let game = load_game(position)?;
+    if let Some(path) = pgn_out {
+        return fs::write(path, pgn::export(&game));
+    }
+    match to {
+        Format::Fen => println!("{}", game.state.to_fen()),
+        Format::Pgn => print!("{}", pgn::export(&game)),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&game).map_err(Error::other)?),
+    }
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        None => play(cli.play),
+        Some(Command::Play(args)) => play(args),
+        Some(Command::Analyze {position, depth, threads: n, weights}) =>
+            analyze(&position, depth, threads(n), weights.as_deref()),
+        Some(Command::Eval {position, weights}) => eval_position(&position, weights.as_deref()),
+        Some(Command::Tune {data, out, weights, iterations, rate}) =>
+            tune_weights(&data, &out, weights.as_deref(), iterations, rate),
+        Some(Command::Review {position, depth, pgn_out, weights}) =>
+            review_game(&position, depth, pgn_out.as_deref(), weights.as_deref()),
+        Some(Command::Perft {depth, fen, position}) => {
+            let position = match fen {
+                Some(fen) => PositionArgs {fen: Some(fen), ..position},
+                None => position,
+            };
+            perft::run(load_game(&position)?.state, depth);
+            Ok(())
+        },
+        #[cfg(feature = "engine")]
+        Some(Command::Uci {depth, threads: n, weights, book}) => {
+            let weights = load_weights(weights.as_deref())?;
+            let book = book.as_deref().map(Book::load).transpose()?;
+            uci::run(std::io::stdin().lock(), std::io::stdout(), depth, threads(n), weights, book.as_ref())
+        },
+        #[cfg(not(feature = "engine"))]
+        Some(Command::Uci {..}) => Err(no_engine()),
+        Some(Command::Convert {position, to, pgn_out}) => convert(&position, to, pgn_out.as_deref()),
+        Some(Command::Games) => list_games(),
+        Some(Command::Tutorial {display}) => tui(ChessWidgetState::tutorial(), &display).map(|_| ()),
+    }
+}
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("tress: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// Autosaving must not interrupt the game, so only the last error is kept to
+// be reported on exit
+fn autosave(state: &ChessWidgetState, save_error: &mut Option<Error>) {
+    // there is nothing to resume in a game without moves or in the tutorial
+    if state.game().state.history().len() == 0 || state.is_tutorial() {
+        return;
+    }
+    if let Err(e) = save::save(state.game()) {
+        *save_error = Some(e);
+    }
+}
+
+// Plays from stdin to stdout, exiting with a status that tells the result
+fn play_headless(mut game: Game, args: &PlayArgs) -> Result<()> {
+    let format = match args.output {
+        Output::Board => headless::BoardFormat::Text,
+        Output::Fen => headless::BoardFormat::Fen,
+    };
+    #[cfg(feature = "engine")]
+    let weights = load_weights(args.weights.as_deref())?;
+    #[cfg(feature = "engine")]
+    let engine_move = |state: &ChessState, depth| engine::search(&mut state.clone(), depth, weights.clone()).best_move;
+    #[cfg(not(feature = "engine"))]
+    let engine_move = |_: &ChessState, _| None;
+    headless::run(&mut game, std::io::stdin().lock(), std::io::stdout(), stderr(), format, engine_move)?;
+    if let Some(path) = &args.pgn_out {
+        fs::write(path, pgn::export(&game))?;
+    }
+    std::process::exit(headless::exit_code(game.outcome()));
+}
+
+fn play(args: PlayArgs) -> Result<()> {
+    let mut game = load_game(&args.position)?;
+    let depth = args.depth.unwrap_or(4);
+    if let Some(side) = args.side {
+        let engine = Player::Engine {depth};
+        (game.white, game.black) = match side {
+            Side::White => (Player::Human, engine),
+            Side::Black => (engine, Player::Human),
+            Side::Both => (Player::Human, Player::Human),
+            Side::None => (engine, engine),
+        };
+    } else if args.depth.is_some() {
+        // a resumed game keeps its players, but may get a stronger engine
+        for player in [&mut game.white, &mut game.black] {
+            if let Player::Engine {..} = player {
+                *player = Player::Engine {depth};
+            }
+        }
+    }
+    #[cfg(not(feature = "engine"))]
+    if game.white != Player::Human || game.black != Player::Human || args.analyze || args.weights.is_some() {
+        return Err(no_engine());
+    }
+    if let Some(control) = &args.time {
+        game.clock = Some(Clock::new(control.clone()));
+    }
+    if args.headless {
+        return play_headless(game, &args);
+    }
+
+    let mut state = ChessWidgetState::from_game(game).hint_depth(depth).threads(threads(args.threads));
+    #[cfg(feature = "engine")]
+    {
+        state = state.weights(load_weights(args.weights.as_deref())?);
+    }
+    if let Some(command) = &args.hint_engine {
+        let mut words = command.split_whitespace();
+        let mut process = std::process::Command::new(words.next().unwrap_or_default());
+        process.args(words);
+        let engine = ExternalEngine::start(process)
+            .map_err(|e| Error::new(e.kind(), format!("could not start '{}': {}", command, e)))?;
+        state = state.hint_engine(engine);
+    }
+    if let Some(path) = &args.book {
+        state = state.book(Book::load(path)?);
+    }
+    if args.analyze {
+        state.apply(Action::Analyze);
+    }
+    let state = tui(state, &args.display)?;
+    if let Some(path) = args.pgn_out {
+        fs::write(path, pgn::export(state.game()))?;
+    }
+    Ok(())
+}
+
+// Runs the terminal user interface until the user quits, returns the
+// final state
+fn tui(state: ChessWidgetState, display: &DisplayArgs) -> Result<ChessWidgetState> {
+    let config = match display.config.clone().or_else(config::default_path) {
+        Some(path) => config::Config::load(&path)?,
+        None => config::Config::default(),
+    };
+    let theme = config.theme(display.theme.as_deref(), display.pieces)?;
+    let color_depth = display.colors.or(config.colors).unwrap_or_else(ColorDepth::detect);
+    let keymap = config.keymap(display.keys.as_deref())?;
 
-fn main() -> Result<()> {
     stderr().execute(EnterAlternateScreen)?;
     enable_raw_mode()?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stderr()))?;
     terminal.clear()?;
 
-    let mut state = ChessWidgetState::new();
+    let mut state = state.keymap(keymap);
+    let mut save_error = None;
+
+    let mut last_tick = Instant::now();
 
     loop {
         terminal.draw(|frame| {
             let area = frame.size();
-            frame.render_stateful_widget(ChessWidget::new(), area, &mut state);
+            frame.render_stateful_widget(ChessWidget::new().theme(theme).color_depth(color_depth), area, &mut state);
         })?;
 
+        // the clocks are driven by the polling timeout below
+        let now = Instant::now();
+        state.tick(now - last_tick);
+        last_tick = now;
+
+        #[cfg(feature = "engine")]
+        if state.engine_move() {
+            autosave(&state, &mut save_error);
+            continue;
+        }
+
         if event::poll(std::time::Duration::from_millis(100))? {
             if let event::Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('q') {
-                    break;
-                }
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('j') {
-                    state.move_cursor(Dir::Down);
-                }
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('k') {
-                    state.move_cursor(Dir::Up);
+                if key.kind != KeyEventKind::Press {
+                    continue;
                 }
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('h') {
-                    state.move_cursor(Dir::Left);
-                }
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('l') {
-                    state.move_cursor(Dir::Right);
-                }
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char(' ') {
-                    state.toggle_select();
-                }
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Esc {
-                    state.quit_select();
+                match state.press(Key::from_event(key)) {
+                    Some(Action::Quit) => {
+                        autosave(&state, &mut save_error);
+                        break;
+                    },
+                    Some(Action::Select | Action::Confirm | Action::Draw | Action::ClaimDraw | Action::Command | Action::Hint) =>
+                        autosave(&state, &mut save_error),
+                    _ => (),
                 }
             }
         }
@@ -56,5 +406,8 @@ fn main() -> Result<()> {
 
     stderr().execute(LeaveAlternateScreen)?;
     disable_raw_mode()?;
-    Ok(())
+    if let Some(e) = save_error {
+        eprintln!("Could not save the game: {}", e);
+    }
+    Ok(state)
 }
@@ -0,0 +1,110 @@
+use crate::state::{Move, Pos};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+// No position has more than 218 legal moves
+pub const MAX_MOVES: usize = 256;
+
+// Placeholder for the unused part of a MoveList
+const NO_MOVE: Move = Move::Normal(Pos {file: 1, rank: 1}, Pos {file: 1, rank: 1}, false);
+
+// A list of moves living on the stack, so that generating moves does not
+// allocate
+#[derive(Clone)]
+pub struct MoveList {
+    moves: [Move; MAX_MOVES],
+    len: usize,
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MoveList {
+    pub fn new() -> Self {
+        MoveList {moves: [NO_MOVE; MAX_MOVES], len: 0}
+    }
+
+    pub fn push(&mut self, mv: Move) {
+        self.moves[self.len] = mv;
+        self.len += 1;
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&Move) -> bool) {
+        let mut kept = 0;
+        for i in 0..self.len {
+            if keep(&self.moves[i]) {
+                self.moves[kept] = self.moves[i];
+                kept += 1;
+            }
+        }
+        self.len = kept;
+    }
+}
+
+impl fmt::Debug for MoveList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+
+    fn deref(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [Move] {
+        &mut self.moves[..self.len]
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct IntoIter {
+    list: MoveList,
+    next: usize,
+}
+
+impl Iterator for IntoIter {
+    type Item = Move;
+
+    fn next(&mut self) -> Option<Move> {
+        let mv = self.list.get(self.next).copied();
+        self.next += 1;
+        mv
+    }
+}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter {list: self, next: 0}
+    }
+}
+
+// Restricts the moves returned by ChessState::legal_moves_filtered
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MoveFilter {
+    All,
+    // moves taking an opponents figure, including en passant
+    Captures,
+    // moves putting the opponents King in check
+    Checks,
+    // moves of the figure on the given position
+    From(Pos),
+}
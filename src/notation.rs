@@ -0,0 +1,248 @@
+use crate::movelist::MoveFilter;
+use crate::state::{ChessState, Color, Figure, Move, Pos, BISHOP, KING, KNIGHT, PAWN, QUEEN, ROOK};
+
+// The letter of a figure in Standard Algebraic Notation, empty for Pawns
+fn san_letter(figure: Figure) -> &'static str {
+    match figure {
+        Figure::Pawn(_,_) => "",
+        Figure::Rook(_,_) => "R",
+        Figure::Knight(_,_) => "N",
+        Figure::Bishop(_,_) => "B",
+        Figure::Queen(_,_) => "Q",
+        Figure::King(_,_) => "K",
+    }
+}
+
+// Formats a legal move of the player to move in Standard Algebraic Notation,
+// e.g. "Nf3", "exd5", "O-O" or "e8=Q+"
+pub fn san(state: &ChessState, mv: Move) -> String {
+    let mut san = match mv {
+        Move::Castle(king_pos, _, king_new_pos, _) => {
+            if king_new_pos.file > king_pos.file { "O-O".to_owned() } else { "O-O-O".to_owned() }
+        },
+        _ => {
+            let from = mv.from_pos();
+            let to = mv.to_pos();
+            let figure = state.board.fields_get(from).unwrap();
+            let mut san = san_letter(figure).to_owned();
+            if let Figure::Pawn(_,_) = figure {
+                if mv.is_capture() {
+                    san.push_str(&from.to_string()[..1]);
+                }
+            } else {
+                // other figures of the same kind that could move there as well
+                let others: Vec<Move> = state.legal_moves()
+                    .into_iter()
+                    .filter(|other| other.to_pos() == to && other.from_pos() != from)
+                    .filter(|other| {
+                        state.board.fields_get(other.from_pos())
+                            .is_some_and(|fig| fig.kind_index() == figure.kind_index())
+                    })
+                    .collect();
+                if !others.is_empty() {
+                    let from_s = from.to_string();
+                    if others.iter().all(|other| other.from_pos().file != from.file) {
+                        san.push_str(&from_s[..1]);
+                    } else if others.iter().all(|other| other.from_pos().rank != from.rank) {
+                        san.push_str(&from_s[1..]);
+                    } else {
+                        san.push_str(&from_s);
+                    }
+                }
+            }
+            if mv.is_capture() {
+                san.push('x');
+            }
+            san.push_str(&to.to_string());
+            if let Move::Promote(_, _, promoted, _) = mv {
+                san.push('=');
+                san.push_str(san_letter(promoted));
+            }
+            san
+        },
+    };
+    if state.gives_check(mv) {
+        let mut after = state.clone();
+        after.make_move(mv);
+        san.push(if after.player_has_moves() { '+' } else { '#' });
+    }
+    san
+}
+
+// Formats a sequence of moves starting in the given position, with move
+// numbers, e.g. "1. e4 e5 2. Nf3"
+pub fn san_line(state: &ChessState, moves: &[Move]) -> String {
+    let mut state = state.clone();
+    let mut line = String::new();
+    for (i, &mv) in moves.iter().enumerate() {
+        if i > 0 {
+            line.push(' ');
+        }
+        match state.turn {
+            Color::White => line.push_str(&format!("{}. ", state.fullmove_number)),
+            Color::Black if i == 0 => line.push_str(&format!("{}... ", state.fullmove_number)),
+            Color::Black => (),
+        }
+        line.push_str(&san(&state, mv));
+        state.make_move(mv);
+    }
+    line
+}
+
+// Finds the legal move given in UCI notation, e.g. "e2e4" or "e7e8q"
+pub fn parse_uci(state: &ChessState, s: &str) -> Result<Move, ()> {
+    if !s.is_ascii() || !(4..=5).contains(&s.len()) {
+        return Err(());
+    }
+    let from: Pos = s[..2].parse()?;
+    let to: Pos = s[2..4].parse()?;
+    let promotion = s[4..].chars().next();
+    state.legal_moves_filtered(MoveFilter::From(from))
+        .into_iter()
+        .find(|mv| {
+            mv.to_pos() == to && match mv {
+                Move::Promote(_, _, fig, _) => promotion == Some(fig.letter().to_ascii_lowercase()),
+                _ => promotion.is_none(),
+            }
+        })
+        .ok_or(())
+}
+
+// Finds the legal move given in Standard Algebraic Notation. Check and
+// annotation symbols are ignored, "0-0" is accepted for castling and the
+// "=" of promotions may be left out.
+pub fn parse_san(state: &ChessState, s: &str) -> Result<Move, ()> {
+    let s = s.trim_end_matches(['+', '#', '!', '?']);
+    if !s.is_ascii() || s.len() < 2 {
+        return Err(());
+    }
+    if s == "O-O" || s == "0-0" || s == "O-O-O" || s == "0-0-0" {
+        let kingside = s.len() == 3;
+        return state.legal_moves()
+            .into_iter()
+            .find(|mv| matches!(mv, Move::Castle(king, _, new_king, _) if (new_king.file > king.file) == kingside))
+            .ok_or(());
+    }
+    let (kind, rest) = match s.as_bytes()[0] {
+        b'R' => (ROOK, &s[1..]),
+        b'N' => (KNIGHT, &s[1..]),
+        b'B' => (BISHOP, &s[1..]),
+        b'Q' => (QUEEN, &s[1..]),
+        b'K' => (KING, &s[1..]),
+        _ => (PAWN, s),
+    };
+    let (rest, promotion) = match rest.as_bytes().last() {
+        Some(letter) if letter.is_ascii_uppercase() => {
+            let promotion = Figure::from_letter(*letter as char, true)?;
+            (rest[..rest.len() - 1].trim_end_matches('='), Some(promotion.kind_index()))
+        },
+        _ => (rest, None),
+    };
+    if rest.len() < 2 {
+        return Err(());
+    }
+    let to: Pos = rest[rest.len() - 2..].parse()?;
+    // disambiguation by file, rank or both
+    let mut from_file = None;
+    let mut from_rank = None;
+    for c in rest[..rest.len() - 2].chars().filter(|&c| c != 'x') {
+        match c {
+            'a'..='h' => from_file = Some(c as usize - 'a' as usize + 1),
+            '1'..='8' => from_rank = Some(c as usize - '0' as usize),
+            _ => return Err(()),
+        }
+    }
+    let mut candidates = state.legal_moves();
+    candidates.retain(|mv| {
+        let from = mv.from_pos();
+        mv.to_pos() == to
+            && !matches!(mv, Move::Castle(_, _, _, _))
+            && state.board.fields_get(from).is_some_and(|fig| fig.kind_index() == kind)
+            && from_file.is_none_or(|file| from.file == file)
+            && from_rank.is_none_or(|rank| from.rank == rank)
+            && match mv {
+                Move::Promote(_, _, fig, _) => promotion == Some(fig.kind_index()),
+                _ => promotion.is_none(),
+            }
+    });
+    match candidates.len() {
+        1 => Ok(candidates[0]),
+        _ => Err(()),
+    }
+}
+
+// Accepts a move in either SAN or UCI notation
+pub fn parse_move(state: &ChessState, s: &str) -> Result<Move, ()> {
+    parse_san(state, s).or_else(|_| parse_uci(state, s))
+}
+
+// Finds the squares and the promotion of a move in UCI or SAN notation, to
+// be checked by move_checked. UCI moves are not checked for legality here.
+pub fn parse_input(state: &ChessState, text: &str) -> Result<(Pos, Pos, Option<Figure>), ()> {
+    if text.is_ascii() && (4..=5).contains(&text.len()) {
+        if let (Ok(from), Ok(to)) = (text[..2].parse::<Pos>(), text[2..4].parse::<Pos>()) {
+            // only a Pawn reaching the last rank is promoted, and not to a King
+            let promotion = match text[4..].chars().next() {
+                Some(letter) => {
+                    let pawn = matches!(state.board.fields_get(from), Some(Figure::Pawn(_, _)));
+                    if !pawn || (to.rank != 1 && to.rank != 8) || !"nbrq".contains(letter.to_ascii_lowercase()) {
+                        return Err(());
+                    }
+                    Some(Figure::from_letter(letter.to_ascii_lowercase(), true)?)
+                },
+                None => None,
+            };
+            return Ok((from, to, promotion));
+        }
+    }
+    let mv = parse_san(state, text)?;
+    let promotion = match mv {
+        Move::Promote(_, _, fig, _) => Some(fig),
+        _ => None,
+    };
+    Ok((mv.from_pos(), mv.to_pos(), promotion))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_round_trip() {
+        let fens = ["r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                    "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+                    "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"];
+        for fen in fens {
+            let state = ChessState::from_fen(fen).unwrap();
+            for mv in state.legal_moves() {
+                assert_eq!(parse_san(&state, &san(&state, mv)), Ok(mv));
+                assert_eq!(parse_uci(&state, &mv.to_string()), Ok(mv));
+            }
+        }
+    }
+
+    #[test]
+    fn parse_variants() {
+        let state = ChessState::new();
+        assert_eq!(parse_move(&state, "e4").unwrap().to_string(), "e2e4");
+        assert_eq!(parse_move(&state, "g1f3").unwrap().to_string(), "g1f3");
+        assert_eq!(parse_move(&state, "Nf3!?").unwrap().to_string(), "g1f3");
+        assert!(parse_move(&state, "e5").is_err());
+        assert!(parse_move(&state, "Nd2").is_err());
+    }
+
+    #[test]
+    fn parse_squares() {
+        let pos = |s: &str| s.parse::<Pos>().unwrap();
+        let state = ChessState::from_fen("k7/4P3/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        // only the kind of the promotion matters
+        let kind = |text| parse_input(&state, text).map(|(from, to, fig)| (from, to, fig.map(|fig| fig.kind_index())));
+        assert_eq!(kind("e7e8n"), Ok((pos("e7"), pos("e8"), Some(KNIGHT))));
+        assert_eq!(kind("e8=Q"), Ok((pos("e7"), pos("e8"), Some(QUEEN))));
+        // not checked for legality
+        assert_eq!(parse_input(&state, "e2e5"), Ok((pos("e2"), pos("e5"), None)));
+        for text in ["e2e4q", "e7e8k", "e1d1r", "Ke3"] {
+            assert_eq!(parse_input(&state, text), Err(()), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::state::{ChessState, Move};
+
+// Counts the leaf nodes of the legal move tree up to the given depth
+pub fn perft(state: &mut ChessState, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = state.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut nodes = 0;
+    for mv in moves {
+        state.make_move(mv);
+        nodes += perft(state, depth - 1);
+        state.unmake_move();
+    }
+    nodes
+}
+
+// Like perft, but split up by the first move
+pub fn divide(state: &mut ChessState, depth: u32) -> Vec<(Move, u64)> {
+    let mut result = vec![];
+    for mv in state.legal_moves() {
+        state.make_move(mv);
+        result.push((mv, perft(state, depth.saturating_sub(1))));
+        state.unmake_move();
+    }
+    result
+}
+
+// Prints the node counts per first move and in total, like other engines do
+// for `go perft`
+pub fn run(mut state: ChessState, depth: u32) {
+    let mut nodes = 0;
+    for (mv, count) in divide(&mut state, depth) {
+        println!("{}: {}", mv, count);
+        nodes += count;
+    }
+    println!();
+    println!("Nodes searched: {}", nodes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Node counts from https://www.chessprogramming.org/Perft_Results
+    fn check(fen: &str, counts: &[u64]) {
+        let mut state = ChessState::from_fen(fen).unwrap();
+        for (depth, &count) in counts.iter().enumerate() {
+            assert_eq!(perft(&mut state, depth as u32 + 1), count, "depth {}", depth + 1);
+        }
+    }
+
+    #[test]
+    fn initial_position() {
+        check("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+              &[20, 400, 8902, 197281, 4865609]);
+    }
+
+    #[test]
+    fn kiwipete() {
+        check("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+              &[48, 2039, 97862, 4085603]);
+    }
+
+    #[test]
+    fn position_3() {
+        check("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+              &[14, 191, 2812, 43238, 674624]);
+    }
+
+    #[test]
+    fn position_4() {
+        check("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+              &[6, 264, 9467, 422333]);
+    }
+
+    #[test]
+    fn position_5() {
+        check("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+              &[44, 1486, 62379, 2103487]);
+    }
+
+    #[test]
+    fn position_6() {
+        check("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+              &[46, 2079, 89890, 3894594]);
+    }
+
+    #[test]
+    fn new_matches_initial_fen() {
+        let mut state = ChessState::new();
+        assert_eq!(perft(&mut state, 2), 400);
+    }
+}
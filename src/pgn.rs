@@ -0,0 +1,284 @@
+use crate::clock::TimeControl;
+use crate::game::{DrawReason, Game, Outcome, Player, WinReason};
+use crate::notation;
+use crate::state::{ChessState, Color};
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// The value of the Result tag, "*" while the game is going on
+pub fn result_tag(outcome: Option<Outcome>) -> &'static str {
+    match outcome {
+        Some(Outcome::Win(Color::White, _)) => "1-0",
+        Some(Outcome::Win(Color::Black, _)) => "0-1",
+        Some(Outcome::Draw(_)) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+fn player_name(player: Player) -> String {
+    match player {
+        Player::Human => "?".to_owned(),
+        Player::Engine {depth} => format!("tress (depth {})", depth),
+    }
+}
+
+// Reverses player_name
+fn parse_player(name: &str) -> Player {
+    name.strip_prefix("tress (depth ")
+        .and_then(|rest| rest.strip_suffix(')'))
+        .and_then(|depth| depth.parse().ok())
+        .map_or(Player::Human, |depth| Player::Engine {depth})
+}
+
+// The TimeControl tag counts in seconds and separates stages with colons
+fn time_control_tag(control: &TimeControl) -> String {
+    let stages: Vec<String> = control.stages
+        .iter()
+        .map(|stage| {
+            let mut tag = String::new();
+            if let Some(moves) = stage.moves {
+                tag.push_str(&format!("{}/", moves));
+            }
+            tag.push_str(&stage.time.as_secs().to_string());
+            if !stage.increment.is_zero() {
+                tag.push_str(&format!("+{}", stage.increment.as_secs()));
+            }
+            tag
+        })
+        .collect();
+    stages.join(":")
+}
+
+// Game ids are the start time in milliseconds since the epoch
+fn date_tag(id: &str) -> String {
+    let millis: u64 = match id.parse() {
+        Ok(millis) => millis,
+        Err(_) => return "????.??.??".to_owned(),
+    };
+    // civil date from days since 1970-01-01, after Howard Hinnant
+    let days = (millis / 86_400_000) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+// Exports the game in PGN export format
+pub fn export(game: &Game) -> String {
+    export_annotated(game, &[])
+}
+
+// Exports the game with annotations like NAGs or comments after the moves,
+// one per move in the history, empty ones are left out
+pub fn export_annotated(game: &Game, annotations: &[String]) -> String {
+    let outcome = game.outcome();
+    let result = result_tag(outcome);
+
+    // the game may have started from a set up position
+    let mut start = game.state.clone();
+    while start.unmake_move().is_some() {}
+    let start_fen = start.to_fen();
+
+    let mut pgn = String::new();
+    let mut tag = |name: &str, value: &str| {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+    };
+    tag("Event", "Casual game");
+    tag("Site", "tress");
+    tag("Date", &date_tag(&game.id));
+    tag("Round", "-");
+    tag("White", &player_name(game.white));
+    tag("Black", &player_name(game.black));
+    tag("Result", result);
+    if start_fen != START_FEN {
+        tag("SetUp", "1");
+        tag("FEN", &start_fen);
+    }
+    if let Some(clock) = &game.clock {
+        tag("TimeControl", &time_control_tag(&clock.control));
+    }
+    match outcome {
+        Some(Outcome::Win(_, WinReason::Timeout)) => tag("Termination", "time forfeit"),
+        Some(_) => tag("Termination", "normal"),
+        None => tag("Termination", "unterminated"),
+    }
+    pgn.push('\n');
+
+    let mut movetext = String::new();
+    let mut annotated = false;
+    for (i, mv) in game.state.history().enumerate() {
+        match start.turn {
+            Color::White => movetext.push_str(&format!("{}. ", start.fullmove_number)),
+            // the move number is repeated after an annotation
+            Color::Black if i == 0 || annotated => movetext.push_str(&format!("{}... ", start.fullmove_number)),
+            Color::Black => (),
+        }
+        movetext.push_str(&notation::san(&start, mv));
+        movetext.push(' ');
+        let annotation = annotations.get(i).map_or("", String::as_str);
+        annotated = !annotation.is_empty();
+        if annotated {
+            movetext.push_str(annotation);
+            movetext.push(' ');
+        }
+        start.make_move(mv);
+    }
+    movetext.push_str(result);
+    pgn.push_str(&wrap(movetext.trim_start(), 79));
+    pgn.push('\n');
+    pgn
+}
+
+// Removes comments, variations and numeric annotation glyphs from movetext
+fn strip_annotations(movetext: &str) -> String {
+    let mut stripped = String::new();
+    let mut in_comment = false;
+    let mut in_line_comment = false;
+    let mut variation_depth = 0;
+    for c in movetext.chars() {
+        match c {
+            _ if in_line_comment => in_line_comment = c != '\n',
+            '}' if in_comment => in_comment = false,
+            _ if in_comment => (),
+            '{' => in_comment = true,
+            ';' => in_line_comment = true,
+            '(' => variation_depth += 1,
+            ')' => variation_depth -= 1,
+            _ if variation_depth > 0 => (),
+            _ => {
+                stripped.push(c);
+                continue;
+            },
+        }
+        stripped.push(' ');
+    }
+    stripped
+}
+
+// Reads the first game of a PGN file. The outcome is taken from the Result
+// tag when it does not follow from the final position.
+pub fn import(pgn: &str) -> Result<Game, ()> {
+    let mut tags = vec![];
+    let mut movetext = String::new();
+    for line in pgn.lines() {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            if !movetext.trim().is_empty() {
+                // the next game begins
+                break;
+            }
+            let (name, value) = line[1..line.len() - 1].split_once(' ').ok_or(())?;
+            let value = value.trim().trim_matches('"').replace("\\\"", "\"").replace("\\\\", "\\");
+            tags.push((name.to_owned(), value));
+        } else {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+    let tag = |name: &str| tags.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str());
+
+    let state = match tag("FEN") {
+        Some(fen) => ChessState::from_fen(fen)?,
+        None => ChessState::new(),
+    };
+    let mut game = Game::new(state,
+                             tag("White").map_or(Player::Human, parse_player),
+                             tag("Black").map_or(Player::Human, parse_player));
+    for token in strip_annotations(&movetext).split_whitespace() {
+        if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token) {
+            continue;
+        }
+        // move numbers may be written together with the move, e.g. "1.e4"
+        let token = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+        if token.is_empty() || token.starts_with('$') {
+            continue;
+        }
+        let mv = notation::parse_san(&game.state, token)?;
+        game.make_move(mv);
+    }
+
+    if game.outcome().is_none() {
+        let timeout = tag("Termination") == Some("time forfeit");
+        let outcome = match tag("Result") {
+            Some("1-0") if timeout => Some(Outcome::Win(Color::White, WinReason::Timeout)),
+            Some("0-1") if timeout => Some(Outcome::Win(Color::Black, WinReason::Timeout)),
+            Some("1-0") => Some(Outcome::Win(Color::White, WinReason::Resignation)),
+            Some("0-1") => Some(Outcome::Win(Color::Black, WinReason::Resignation)),
+            Some("1/2-1/2") => Some(Outcome::Draw(DrawReason::Agreement)),
+            _ => None,
+        };
+        if let Some(outcome) = outcome {
+            game.set_result(outcome);
+        }
+    }
+    Ok(game)
+}
+
+// Breaks the text into lines of at most the given width between tokens
+fn wrap(text: &str, width: usize) -> String {
+    let mut wrapped = String::new();
+    let mut line_len = 0;
+    for token in text.split(' ') {
+        if line_len > 0 && line_len + 1 + token.len() > width {
+            wrapped.push('\n');
+            line_len = 0;
+        } else if line_len > 0 {
+            wrapped.push(' ');
+            line_len += 1;
+        }
+        wrapped.push_str(token);
+        line_len += token.len();
+    }
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ChessState;
+
+    #[test]
+    fn export_resigned_game() {
+        let mut game = Game::new(ChessState::new(), Player::Human, Player::Engine {depth: 3});
+        game.id = "1700000000000".to_owned();
+        for (from, to) in [("e2", "e4"), ("e7", "e5"), ("g1", "f3"), ("b8", "c6"), ("f1", "b5"), ("g8", "f6"),
+                           ("e1", "g1"), ("f6", "e4"), ("d2", "d4"), ("e4", "d6"), ("b5", "c6"), ("d7", "c6")] {
+            game.move_checked(from.parse().unwrap(), to.parse().unwrap()).unwrap();
+        }
+        game.resign(Color::White).unwrap();
+        let pgn = export(&game);
+        assert!(pgn.contains("[Date \"2023.11.14\"]\n"));
+        assert!(pgn.contains("[Result \"0-1\"]\n"));
+        assert!(pgn.ends_with("\n\n1. e4 e5 2. Nf3 Nc6 3. Bb5 Nf6 4. O-O Nxe4 5. d4 Nd6 6. Bxc6 dxc6 0-1\n"));
+    }
+
+    #[test]
+    fn import_exported_game() {
+        let pgn = "[Event \"Casual game\"]\n[White \"tress (depth 3)\"]\n[Result \"0-1\"]\n\n\
+                   1. e4 {best by test} e5 2.Nf3 Nc6 (2... d6) 3. Bb5 $1 Nf6 4. O-O Nxe4 ; Berlin\n\
+                   5. d4 Nd6 6. Bxc6 dxc6 0-1\n";
+        let game = import(pgn).unwrap();
+        assert_eq!(game.white, Player::Engine {depth: 3});
+        assert_eq!(game.state.history().len(), 12);
+        assert_eq!(game.outcome(), Some(Outcome::Win(Color::Black, WinReason::Resignation)));
+        let again = import(&export(&game)).unwrap();
+        assert_eq!(again.state, game.state);
+        assert!(import("1. e4 e4").is_err());
+    }
+
+    #[test]
+    fn export_set_up_position() {
+        let state = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut game = Game::new(state, Player::Human, Player::Human);
+        game.move_checked("a1".parse().unwrap(), "a8".parse().unwrap()).unwrap();
+        let pgn = export(&game);
+        assert!(pgn.contains("[FEN \"6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1\"]\n"));
+        assert!(pgn.ends_with("\n\n1. Ra8# 1-0\n"));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::notation;
+use crate::state::ChessState;
+use std::path::PathBuf;
+
+// Commands typed at the prompt, as opposed to moves
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    // shows the position in Forsyth-Edwards Notation
+    Fen,
+    // turns the board around
+    Flip,
+    // takes back the last move, or the last two against the engine
+    Undo,
+    // writes the game as PGN
+    Save(PathBuf),
+    // sets the depth the engine searches to
+    EngineDepth(u32),
+    // asks the engine for a move
+    Hint,
+}
+
+pub const COMMANDS: [&str; 6] = ["fen", "flip", "undo", "save", "engine", "hint"];
+
+// How to use the commands, for the help overlay
+pub const HELP: [(&str, &str); 6] = [("fen", "show the position in FEN"),
+                                     ("flip", "turn the board around"),
+                                     ("undo", "take back the last move"),
+                                     ("save FILE", "write the game to a PGN file"),
+                                     ("engine depth N", "let the engine search N half moves deep"),
+                                     ("hint", "ask the engine for a move")];
+
+impl Command {
+    // Parses a command, Ok(None) if the text is no command but maybe a move
+    pub fn parse(text: &str) -> Result<Option<Command>, String> {
+        let mut words = text.split_whitespace();
+        let command = match words.next() {
+            Some(command) if COMMANDS.contains(&command) => command,
+            _ => return Ok(None),
+        };
+        let args: Vec<&str> = words.collect();
+        let command = match (command, args.as_slice()) {
+            ("fen", []) => Command::Fen,
+            ("flip", []) => Command::Flip,
+            ("undo", []) => Command::Undo,
+            ("hint", []) => Command::Hint,
+            ("save", [_, ..]) => {
+                // the file name may contain spaces
+                let path = text.trim_start().strip_prefix("save").unwrap_or_default().trim();
+                Command::Save(PathBuf::from(path))
+            },
+            ("engine", ["depth", depth]) => match depth.parse() {
+                Ok(depth) if depth > 0 => Command::EngineDepth(depth),
+                _ => return Err(format!("invalid depth '{}'", depth)),
+            },
+            ("save", _) => return Err("usage: save FILE".to_owned()),
+            ("engine", _) => return Err("usage: engine depth N".to_owned()),
+            (command, _) => return Err(format!("{} takes no arguments", command)),
+        };
+        Ok(Some(command))
+    }
+}
+
+// The legal moves in SAN and UCI notation and the commands that start with
+// the text, SAN first
+pub fn completions(state: &ChessState, text: &str) -> Vec<String> {
+    let moves = state.legal_moves();
+    let san = moves.iter().map(|mv| notation::san(state, *mv).trim_end_matches(['+', '#']).to_owned());
+    let uci = moves.iter().map(|mv| mv.to_string());
+    let commands = COMMANDS.iter().map(|command| command.to_string());
+    let mut completions: Vec<String> = vec![];
+    for completion in san.chain(uci).chain(commands) {
+        if completion.starts_with(text) && !completions.contains(&completion) {
+            completions.push(completion);
+        }
+    }
+    completions
+}
+
+// The longest text all completions start with
+pub fn complete(completions: &[String]) -> Option<String> {
+    let (first, rest) = completions.split_first()?;
+    let mut prefix = first.as_str();
+    for completion in rest {
+        let len = prefix
+            .char_indices()
+            .zip(completion.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(completion.len()), |((i, _), _)| i);
+        prefix = &prefix[..len];
+    }
+    Some(prefix.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("flip"), Ok(Some(Command::Flip)));
+        assert_eq!(Command::parse(" save my game.pgn "), Ok(Some(Command::Save(PathBuf::from("my game.pgn")))));
+        assert_eq!(Command::parse("engine depth 8"), Ok(Some(Command::EngineDepth(8))));
+        assert_eq!(Command::parse("Nf3"), Ok(None));
+        assert!(Command::parse("engine depth x").is_err());
+        assert!(Command::parse("save").is_err());
+        assert!(Command::parse("undo 2").is_err());
+    }
+
+    #[test]
+    fn complete_moves() {
+        let state = ChessState::new();
+        assert_eq!(completions(&state, "N"), ["Na3", "Nc3", "Nf3", "Nh3"]);
+        assert_eq!(completions(&state, "g1"), ["g1f3", "g1h3"]);
+        assert_eq!(completions(&state, "f"), ["f3", "f4", "f2f3", "f2f4", "fen", "flip"]);
+        assert_eq!(complete(&completions(&state, "g1")).unwrap(), "g1");
+        assert_eq!(complete(&completions(&state, "u")).unwrap(), "undo");
+        assert_eq!(complete(&completions(&state, "h")).unwrap(), "h");
+        assert_eq!(complete(&completions(&state, "fl")).unwrap(), "flip");
+        assert_eq!(complete(&completions(&state, "x")), None);
+    }
+}
@@ -1,34 +1,490 @@
-use crate::state::{self, Dir, Pos, ChessState};
+#[cfg(feature = "engine")]
+use crate::analysis::{self, Analysis, Evaluation};
+#[cfg(feature = "engine")]
+use crate::engine::Thinking;
+#[cfg(feature = "engine")]
+use crate::eval::Weights;
+#[cfg(feature = "engine")]
+use crate::review::{self, Reviewer};
+use crate::book::Book;
+use crate::clock;
+use crate::external::{BackgroundEngine, ExternalEngine};
+use crate::game::{Game, Player};
+use crate::keymap::{Action, Key, Keymap};
+use crate::notation;
+use crate::pgn;
+use crate::prompt::{self, Command};
+use crate::tutorial::{self, Tutorial};
+use crate::movelist::MoveFilter;
+use crate::state::{self, Dir, Pos, ChessState, Move};
+use std::borrow::Cow;
+use std::fs;
+use std::str::FromStr;
+#[cfg(feature = "engine")]
+use std::sync::Arc;
+use std::time::Duration;
+use crossterm::event::{KeyCode, KeyModifiers};
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
-    widgets::StatefulWidget,
+    widgets::{Block, Borders, Clear, Paragraph, StatefulWidget, Widget},
     style,
     prelude::Style,
 };
 
-pub struct ChessWidget {}
+// How figures are drawn
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PieceSet {
+    // filled Unicode chess symbols for both colors
+    Filled,
+    // outlined Unicode chess symbols for both colors
+    Outline,
+    // FEN letters, upper case for White
+    Letters,
+    // FEN letters, with empty dark squares marked by '.' so the board can be
+    // read without colours
+    Ascii,
+}
+
+impl PieceSet {
+    pub fn symbol(&self, figure: state::Figure) -> String {
+        use state::Figure::*;
+        match self {
+            PieceSet::Filled => figure.symbol().to_owned(),
+            PieceSet::Outline => match figure {
+                Pawn(_,_) => "♙",
+                Rook(_,_) => "♖",
+                Knight(_,_) => "♘",
+                Bishop(_,_) => "♗",
+                Queen(_,_) => "♕",
+                King(_,_) => "♔",
+            }.to_owned(),
+            PieceSet::Letters | PieceSet::Ascii => figure.letter().to_string(),
+        }
+    }
+
+    // What is drawn on an empty square
+    fn empty_symbol(&self, dark: bool) -> &'static str {
+        match self {
+            PieceSet::Ascii if dark => ".",
+            _ => " ",
+        }
+    }
+}
+
+impl FromStr for PieceSet {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "filled" => Ok(PieceSet::Filled),
+            "outline" => Ok(PieceSet::Outline),
+            "letters" => Ok(PieceSet::Letters),
+            "ascii" => Ok(PieceSet::Ascii),
+            _ => Err(()),
+        }
+    }
+}
+
+// The colours the board is drawn in, and the piece set
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Theme {
+    pub light_square: style::Color,
+    pub dark_square: style::Color,
+    pub white_figure: style::Color,
+    pub black_figure: style::Color,
+    // squares the selected figure may move to
+    pub target: style::Color,
+    pub selected: style::Color,
+    // the from and to squares of a hint
+    pub hint: style::Color,
+    // the cursor takes the colour of the player to move
+    pub white_cursor: style::Color,
+    pub black_cursor: style::Color,
+    pub pieces: PieceSet,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::classic()
+    }
+}
+
+impl Theme {
+    pub const PRESETS: [&'static str; 4] = ["classic", "blue", "green", "high-contrast"];
+
+    pub fn classic() -> Self {
+        Theme {light_square: style::Color::Rgb(227,193,111),
+               dark_square: style::Color::Rgb(184,139,74),
+               white_figure: style::Color::White,
+               black_figure: style::Color::Black,
+               target: style::Color::LightGreen,
+               selected: style::Color::Green,
+               hint: style::Color::LightCyan,
+               white_cursor: style::Color::Blue,
+               black_cursor: style::Color::Red,
+               pieces: PieceSet::Filled}
+    }
+
+    pub fn blue() -> Self {
+        Theme {light_square: style::Color::Rgb(222,227,230),
+               dark_square: style::Color::Rgb(140,162,173),
+               target: style::Color::Rgb(155,199,0),
+               selected: style::Color::Rgb(100,140,30),
+               hint: style::Color::Rgb(230,150,60),
+               white_cursor: style::Color::Rgb(20,85,30),
+               black_cursor: style::Color::Rgb(170,50,50),
+               ..Self::classic()}
+    }
+
+    pub fn green() -> Self {
+        Theme {light_square: style::Color::Rgb(238,238,210),
+               dark_square: style::Color::Rgb(118,150,86),
+               target: style::Color::Rgb(246,246,105),
+               selected: style::Color::Rgb(186,202,68),
+               hint: style::Color::Rgb(100,180,220),
+               white_cursor: style::Color::Rgb(60,90,160),
+               black_cursor: style::Color::Rgb(180,60,60),
+               ..Self::classic()}
+    }
+
+    // Pure black and white figures on grey squares, with bright markers
+    pub fn high_contrast() -> Self {
+        Theme {light_square: style::Color::Rgb(200,200,200),
+               dark_square: style::Color::Rgb(90,90,90),
+               white_figure: style::Color::Rgb(255,255,255),
+               black_figure: style::Color::Rgb(0,0,0),
+               target: style::Color::Rgb(0,255,255),
+               selected: style::Color::Rgb(0,255,0),
+               hint: style::Color::Rgb(255,128,0),
+               white_cursor: style::Color::Rgb(255,255,0),
+               black_cursor: style::Color::Rgb(255,0,255),
+               pieces: PieceSet::Filled}
+    }
+
+    // The theme with its colours mapped to ones the terminal can show
+    pub fn reduced(&self, depth: ColorDepth) -> Self {
+        let reduce = |color| reduce_color(color, depth);
+        Theme {light_square: reduce(self.light_square),
+               dark_square: reduce(self.dark_square),
+               white_figure: reduce(self.white_figure),
+               black_figure: reduce(self.black_figure),
+               target: reduce(self.target),
+               selected: reduce(self.selected),
+               hint: reduce(self.hint),
+               white_cursor: reduce(self.white_cursor),
+               black_cursor: reduce(self.black_cursor),
+               pieces: self.pieces}
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "classic" => Some(Self::classic()),
+            "blue" => Some(Self::blue()),
+            "green" => Some(Self::green()),
+            "high-contrast" => Some(Self::high_contrast()),
+            _ => None,
+        }
+    }
+}
+
+// Themes are chosen by the name of their preset
+impl FromStr for Theme {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Self::preset(s).ok_or(())
+    }
+}
+
+// How many colours the terminal can show
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    // no colours at all, squares are told apart by reverse video
+    Monochrome,
+}
+
+impl ColorDepth {
+    // Guesses the colour depth from the environment like other terminal
+    // programs do
+    pub fn detect() -> Self {
+        let var = |name| std::env::var(name).ok();
+        Self::from_env(var("NO_COLOR").as_deref(), var("COLORTERM").as_deref(), var("TERM").as_deref())
+    }
+
+    fn from_env(no_color: Option<&str>, colorterm: Option<&str>, term: Option<&str>) -> Self {
+        // see https://no-color.org
+        if no_color.is_some_and(|value| !value.is_empty()) {
+            return ColorDepth::Monochrome;
+        }
+        if matches!(colorterm, Some("truecolor") | Some("24bit")) {
+            return ColorDepth::TrueColor;
+        }
+        match term {
+            None | Some("dumb") => ColorDepth::Monochrome,
+            Some(term) if term.contains("direct") => ColorDepth::TrueColor,
+            Some(term) if term.contains("256color") => ColorDepth::Ansi256,
+            Some(_) => ColorDepth::Ansi16,
+        }
+    }
+}
+
+impl FromStr for ColorDepth {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "truecolor" | "24bit" => Ok(ColorDepth::TrueColor),
+            "256" => Ok(ColorDepth::Ansi256),
+            "16" => Ok(ColorDepth::Ansi16),
+            "mono" | "monochrome" => Ok(ColorDepth::Monochrome),
+            _ => Err(()),
+        }
+    }
+}
+
+// The ANSI colours as xterm shows them by default
+const ANSI_16: [(style::Color, (u8, u8, u8)); 16] = [
+    (style::Color::Black, (0, 0, 0)),
+    (style::Color::Red, (205, 0, 0)),
+    (style::Color::Green, (0, 205, 0)),
+    (style::Color::Yellow, (205, 205, 0)),
+    (style::Color::Blue, (0, 0, 238)),
+    (style::Color::Magenta, (205, 0, 205)),
+    (style::Color::Cyan, (0, 205, 205)),
+    (style::Color::Gray, (229, 229, 229)),
+    (style::Color::DarkGray, (127, 127, 127)),
+    (style::Color::LightRed, (255, 0, 0)),
+    (style::Color::LightGreen, (0, 255, 0)),
+    (style::Color::LightYellow, (255, 255, 0)),
+    (style::Color::LightBlue, (92, 92, 255)),
+    (style::Color::LightMagenta, (255, 0, 255)),
+    (style::Color::LightCyan, (0, 255, 255)),
+    (style::Color::White, (255, 255, 255)),
+];
+
+// The steps of the colour cube of the 256-colour palette
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// The RGB value of a colour of the 256-colour palette
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => ANSI_16[index as usize].1,
+        16..=231 => {
+            let i = index - 16;
+            (CUBE[i as usize / 36], CUBE[i as usize / 6 % 6], CUBE[i as usize % 6])
+        },
+        _ => {
+            let gray = 8 + 10 * (index - 232);
+            (gray, gray, gray)
+        },
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+// The closest colour of the cube and the grey ramp of the 256-colour palette
+fn nearest_256(rgb: (u8, u8, u8)) -> u8 {
+    (16..=255).min_by_key(|&index| distance(rgb, indexed_rgb(index))).unwrap()
+}
+
+fn nearest_16(rgb: (u8, u8, u8)) -> style::Color {
+    ANSI_16.iter().min_by_key(|(_, ansi)| distance(rgb, *ansi)).unwrap().0
+}
+
+// Maps a colour to one the terminal can show, named colours are shown by
+// every colour terminal
+fn reduce_color(color: style::Color, depth: ColorDepth) -> style::Color {
+    match (color, depth) {
+        (style::Color::Rgb(r, g, b), ColorDepth::Ansi256) => style::Color::Indexed(nearest_256((r, g, b))),
+        (style::Color::Rgb(r, g, b), ColorDepth::Ansi16) => nearest_16((r, g, b)),
+        (style::Color::Indexed(index), ColorDepth::Ansi16) => nearest_16(indexed_rgb(index)),
+        _ => color,
+    }
+}
+
+pub struct ChessWidget {
+    theme: Theme,
+    color_depth: ColorDepth,
+}
+
+impl Default for ChessWidget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ChessWidget {
     pub fn new() -> Self {
-        Self {}
+        Self {theme: Theme::classic(), color_depth: ColorDepth::TrueColor}
+    }
+
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    pub fn color_depth(mut self, color_depth: ColorDepth) -> Self {
+        self.color_depth = color_depth;
+        self
     }
 }
 
 pub struct ChessWidgetState {
-    chess_state: ChessState,
+    game: Game,
     cursor: Pos,
     selected: Option<Pos>,
+    // shown below the board
+    message: Option<String>,
+    // resigning has to be confirmed
+    resign_pending: bool,
+    keymap: Keymap,
+    // the number of half moves shown while browsing the moves played so
+    // far, None for the current position
+    view: Option<usize>,
+    // the key bindings are shown over the board
+    show_help: bool,
+    // the text typed at the prompt while it is open
+    prompt: Option<String>,
+    // why the text at the prompt was rejected
+    prompt_error: Option<String>,
+    // Black is at the bottom of the board
+    flipped: bool,
+    // the lessons of the tutorial instead of a game
+    tutorial: Option<Tutorial>,
+    // evaluates the position on the board while it is shown
+    #[cfg(feature = "engine")]
+    analysis: Option<Analysis>,
+    // checks the moves once the game has ended
+    #[cfg(feature = "engine")]
+    review: Option<Reviewer>,
+    // the move suggested for the position, until it changes
+    hint: Option<(ChessState, Move)>,
+    // gives hints instead of the built-in engine, thinking about them in
+    // the background
+    hint_engine: Option<BackgroundEngine>,
+    // how deep the built-in engine searches for hints
+    hint_depth: u32,
+    // the built-in engine's search for a hint, until it is done or the
+    // position changes
+    #[cfg(feature = "engine")]
+    hint_search: Option<Thinking>,
+    // the engine searches with
+    threads: usize,
+    // the engine evaluates positions with
+    #[cfg(feature = "engine")]
+    weights: Arc<Weights>,
+    // the engine plays from it while the position is in it, its moves are
+    // shown beside the board
+    book: Option<Book>,
+    // the engine player's search, running while it is its turn
+    #[cfg(feature = "engine")]
+    thinking: Option<Thinking>,
+}
+
+// How long an external engine thinks about a hint
+const HINT_TIME: Duration = Duration::from_secs(1);
+
+impl Default for ChessWidgetState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ChessWidgetState {
     pub fn new() -> Self {
-        Self {chess_state: ChessState::new(),
+        Self::from_game(Game::new(ChessState::new(), Player::Human, Player::Human))
+    }
+
+    pub fn from_game(game: Game) -> Self {
+        Self {game,
               cursor: Pos::new(5, 2).unwrap(),
-              selected: None}
+              selected: None,
+              message: None,
+              resign_pending: false,
+              keymap: Keymap::default(),
+              view: None,
+              show_help: false,
+              prompt: None,
+              prompt_error: None,
+              flipped: false,
+              tutorial: None,
+              #[cfg(feature = "engine")]
+              analysis: None,
+              #[cfg(feature = "engine")]
+              review: None,
+              hint: None,
+              hint_engine: None,
+              hint_depth: 4,
+              #[cfg(feature = "engine")]
+              hint_search: None,
+              threads: 1,
+              #[cfg(feature = "engine")]
+              weights: Arc::new(Weights::default()),
+              book: None,
+              #[cfg(feature = "engine")]
+              thinking: None}
+    }
+
+    // Starts the tutorial, where moves are checked against the task of
+    // each lesson
+    pub fn tutorial() -> Self {
+        let tutorial = Tutorial::new();
+        let game = Game::new(tutorial.position().unwrap(), Player::Human, Player::Human);
+        let mut state = Self::from_game(game);
+        state.tutorial = Some(tutorial);
+        state
+    }
+
+    pub fn is_tutorial(&self) -> bool {
+        self.tutorial.is_some()
+    }
+
+    pub fn keymap(mut self, keymap: Keymap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    // Asks the engine for hints instead of the built-in one
+    pub fn hint_engine(mut self, engine: ExternalEngine) -> Self {
+        self.hint_engine = Some(BackgroundEngine::new(engine, HINT_TIME));
+        self
+    }
+
+    pub fn hint_depth(mut self, depth: u32) -> Self {
+        self.hint_depth = depth;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    #[cfg(feature = "engine")]
+    pub fn weights(mut self, weights: Arc<Weights>) -> Self {
+        self.weights = weights;
+        self
     }
 
+    pub fn book(mut self, book: Book) -> Self {
+        self.book = Some(book);
+        self
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    // Moves the cursor in the direction seen on the screen
     pub fn move_cursor(&mut self, dir: Dir) {
+        let dir = if self.flipped { opposite(dir) } else { dir };
         self.cursor = match self.cursor.move_dir(dir) {
             Ok(pos) => pos,
             Err(_) => self.cursor,
@@ -36,14 +492,24 @@ impl ChessWidgetState {
     }
 
     pub fn toggle_select(&mut self) {
-        if !self.chess_state.player_has_moves() {
+        if self.view.take().is_some() {
+            // selecting goes back to the current position first
+            return;
+        }
+        if self.game.is_finished() || self.game.current_player() != Player::Human {
             return;
         }
         if let Some(sel) = self.selected {
             if self.cursor != sel {
-                match self.chess_state.move_checked(sel, self.cursor) {
-                    Ok(()) => (),
-                    Err(()) => (),
+                match self.game.move_checked(sel, self.cursor) {
+                    Ok(()) => {
+                        self.message = None;
+                        self.check_lesson();
+                    },
+                    Err(e) => {
+                        let hint = self.lesson_hint().map(|hint| format!(" {}", hint)).unwrap_or_default();
+                        self.message = Some(format!("Illegal move: {}.{}", e, hint));
+                    },
                 }
             }
             self.selected = None;
@@ -52,9 +518,499 @@ impl ChessWidgetState {
         }
     }
 
+    fn lesson_hint(&self) -> Option<&'static str> {
+        Some(self.tutorial.as_ref()?.lesson()?.hint)
+    }
+
+    // Goes on to the next lesson if the move solved the task, otherwise
+    // takes the move back and gives a hint
+    fn check_lesson(&mut self) {
+        let hint = self.lesson_hint();
+        let (tutorial, mv) = match (&mut self.tutorial, self.game.state.history().next_back()) {
+            (Some(tutorial), Some(mv)) => (tutorial, mv),
+            _ => return,
+        };
+        if !tutorial.check(mv) {
+            let _ = self.game.undo();
+            self.message = hint.map(|hint| format!("Not quite. {}", hint));
+            return;
+        }
+        match tutorial.position() {
+            Some(position) => {
+                self.game = Game::new(position, Player::Human, Player::Human);
+                self.message = Some("Well done!".to_owned());
+            },
+            None => {
+                self.message = Some(format!("Well done, that was the last lesson. Press '{}' to quit.",
+                                            self.first_key(Action::Quit)));
+            },
+        }
+    }
+
     pub fn quit_select(&mut self) {
         self.selected = None;
     }
+
+    // The side the commands of the user act for: the player to move, unless
+    // that is the engine
+    fn user_color(&self) -> state::Color {
+        let turn = self.game.state.turn;
+        match self.game.current_player() {
+            Player::Human => turn,
+            Player::Engine {..} => turn.other(),
+        }
+    }
+
+    // Asks for confirmation first, see confirm
+    pub fn resign(&mut self) {
+        if self.game.is_finished() {
+            return;
+        }
+        self.resign_pending = true;
+        self.message = Some(format!("Really resign? Press '{}' to confirm.", self.first_key(Action::Confirm)));
+    }
+
+    // Confirms a pending resignation, any other command cancels it
+    pub fn confirm(&mut self) {
+        if self.resign_pending {
+            self.resign_pending = false;
+            self.message = None;
+            let _ = self.game.resign(self.user_color());
+        }
+    }
+
+    pub fn cancel_pending(&mut self) {
+        if self.resign_pending {
+            self.resign_pending = false;
+            self.message = None;
+        }
+    }
+
+    // Accepts the opponents draw offer, or offers a draw
+    pub fn draw(&mut self) {
+        let color = self.user_color();
+        if self.game.accept_draw(color).is_ok() {
+            return;
+        }
+        self.message = match self.game.offer_draw(color) {
+            Ok(()) => Some(format!("{} offers a draw.", color_name(color))),
+            Err(()) => None,
+        };
+    }
+
+    pub fn claim_draw(&mut self) {
+        if self.game.claim_draw().is_err() {
+            self.message = Some("There is no draw to claim.".to_owned());
+        }
+    }
+
+    // Feeds a key press into the key map and carries out the action once a
+    // whole key sequence has been typed. Quitting is left to the caller.
+    pub fn press(&mut self, key: Key) -> Option<Action> {
+        if self.prompt.is_some() {
+            return self.prompt_key(key);
+        }
+        let action = self.keymap.press(key)?;
+        self.apply(action);
+        Some(action)
+    }
+
+    pub fn apply(&mut self, action: Action) {
+        if self.show_help {
+            // any key closes the help
+            self.show_help = false;
+            return;
+        }
+        if action != Action::Confirm {
+            self.cancel_pending();
+        }
+        match action {
+            Action::Up => self.move_cursor(Dir::Up),
+            Action::Down => self.move_cursor(Dir::Down),
+            Action::Left => self.move_cursor(Dir::Left),
+            Action::Right => self.move_cursor(Dir::Right),
+            Action::UpLeft => self.move_cursor(Dir::LeftUp),
+            Action::UpRight => self.move_cursor(Dir::RightUp),
+            Action::DownLeft => self.move_cursor(Dir::LeftDown),
+            Action::DownRight => self.move_cursor(Dir::RightDown),
+            Action::Select => self.toggle_select(),
+            Action::Cancel => self.quit_select(),
+            Action::Quit => self.stop_thinking(),
+            Action::Resign => self.resign(),
+            Action::Confirm => self.confirm(),
+            Action::Draw => self.draw(),
+            Action::ClaimDraw => self.claim_draw(),
+            Action::Help => self.show_help = true,
+            Action::Command => {
+                self.prompt = Some(String::new());
+                self.prompt_error = None;
+            },
+            Action::Analyze => self.toggle_analysis(),
+            Action::Hint => self.hint(),
+            Action::GameStart => self.set_view(Some(0)),
+            Action::MoveBack => {
+                let plies = self.view.unwrap_or(self.game.state.history().len());
+                self.set_view(Some(plies.saturating_sub(1)));
+            },
+            Action::MoveForward => self.set_view(self.view.map(|plies| plies + 1)),
+            Action::GameEnd => self.set_view(None),
+        }
+    }
+
+    // Edits the text at the prompt, returns Action::Command once a move or
+    // command has been carried out
+    fn prompt_key(&mut self, key: Key) -> Option<Action> {
+        let text = self.prompt.as_mut()?;
+        self.prompt_error = None;
+        match key.code {
+            KeyCode::Esc => self.prompt = None,
+            // deleting past the start closes the prompt
+            KeyCode::Backspace if text.pop().is_none() => self.prompt = None,
+            KeyCode::Tab => {
+                if let Some(completed) = prompt::complete(&prompt::completions(&self.game.state, text)) {
+                    if completed.len() > text.len() {
+                        *text = completed;
+                    }
+                }
+            },
+            KeyCode::Enter => {
+                let text = text.clone();
+                match self.run_prompt(text.trim()) {
+                    Ok(()) => {
+                        self.prompt = None;
+                        return Some(Action::Command);
+                    },
+                    Err(e) => self.prompt_error = Some(e),
+                }
+            },
+            KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => text.push(c),
+            _ => (),
+        }
+        None
+    }
+
+    // Plays the move or carries out the command typed at the prompt
+    fn run_prompt(&mut self, text: &str) -> Result<(), String> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        match Command::parse(text)? {
+            Some(command) => self.run_command(command),
+            None => self.type_move(text),
+        }
+    }
+
+    fn type_move(&mut self, text: &str) -> Result<(), String> {
+        if !self.game.is_finished() && self.game.current_player() != Player::Human {
+            return Err("it is not your turn".to_owned());
+        }
+        let (from, to, promotion) = notation::parse_input(&self.game.state, text)
+            .map_err(|_| format!("illegal move '{}'", text))?;
+        let promotion = promotion.unwrap_or(state::Figure::Queen(self.game.state.turn, true));
+        self.game.promote_checked(from, to, promotion)
+            .map_err(|e| format!("illegal move '{}': {}", text, e))?;
+        self.view = None;
+        self.selected = None;
+        self.message = None;
+        self.check_lesson();
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Fen => self.message = Some(self.shown_state().to_fen()),
+            Command::Flip => self.flipped = !self.flipped,
+            Command::Undo => {
+                self.game.undo().map_err(|_| "there is no move to take back".to_owned())?;
+                self.stop_thinking();
+                // against the engine take back its reply as well
+                if self.game.white == Player::Human || self.game.black == Player::Human {
+                    while self.game.current_player() != Player::Human && self.game.undo().is_ok() {}
+                }
+                self.view = None;
+                self.selected = None;
+                self.message = None;
+            },
+            Command::Save(path) => {
+                fs::write(&path, self.pgn())
+                    .map_err(|e| format!("could not save to {}: {}", path.display(), e))?;
+                self.message = Some(format!("Saved the game to {}.", path.display()));
+            },
+            Command::EngineDepth(depth) => {
+                let mut found = false;
+                for player in [&mut self.game.white, &mut self.game.black] {
+                    if let Player::Engine {..} = player {
+                        *player = Player::Engine {depth};
+                        found = true;
+                    }
+                }
+                if !found {
+                    return Err("there is no engine player".to_owned());
+                }
+                self.message = Some(format!("The engine searches {} half moves deep.", depth));
+            },
+            Command::Hint => self.find_hint()?,
+        }
+        Ok(())
+    }
+
+    pub fn hint(&mut self) {
+        if let Err(e) = self.find_hint() {
+            let mut chars = e.chars();
+            let first = chars.next().map(|c| c.to_uppercase().collect::<String>()).unwrap_or_default();
+            self.message = Some(format!("{}{}.", first, chars.as_str()));
+        }
+    }
+
+    // Asks the engine for the best move of the player to move and counts
+    // the hint for them, asking again in the same position is free. The
+    // engine thinks in the background, tick gives the hint once it is
+    // found.
+    fn find_hint(&mut self) -> Result<(), String> {
+        if !self.is_live() || self.game.current_player() != Player::Human {
+            return Err("there is nothing to hint at".to_owned());
+        }
+        let state = self.game.state.clone();
+        match self.hint.as_ref() {
+            Some((hinted, mv)) if *hinted == state => {
+                self.message = Some(format!("Hint: {}.", notation::san(&state, *mv)));
+                return Ok(());
+            },
+            _ => (),
+        }
+        match &mut self.hint_engine {
+            Some(engine) => {
+                engine.ask(&state);
+                self.message = Some("Looking for a hint...".to_owned());
+                Ok(())
+            },
+            None => self.search_hint(state),
+        }
+    }
+
+    #[cfg(feature = "engine")]
+    fn search_hint(&mut self, state: ChessState) -> Result<(), String> {
+        if self.hint_search.as_ref().map(Thinking::state) != Some(&state) {
+            self.hint_search = Some(Thinking::start(&state, self.hint_depth, self.threads, self.weights.clone()));
+        }
+        self.message = Some("Looking for a hint...".to_owned());
+        Ok(())
+    }
+
+    #[cfg(not(feature = "engine"))]
+    fn search_hint(&mut self, _state: ChessState) -> Result<(), String> {
+        Err("there is no engine in this build".to_owned())
+    }
+
+    // Gives the hint once the engine has found it, the search is given up
+    // when the position changes
+    fn update_hint(&mut self) {
+        #[cfg(feature = "engine")]
+        self.update_hint_search();
+        let live = self.is_live();
+        let engine = match &mut self.hint_engine {
+            Some(engine) => engine,
+            None => return,
+        };
+        match engine.position() {
+            Some(position) if !live || *position != self.game.state => engine.cancel(),
+            Some(_) => (),
+            None => return,
+        }
+        let message = match engine.answer() {
+            Some(Ok(Some(mv))) => return self.give_hint(self.game.state.clone(), mv),
+            Some(Ok(None)) => "There is no move to play.".to_owned(),
+            Some(Err(e)) => format!("{} failed: {}.", engine.name, e),
+            None => return,
+        };
+        self.message = Some(message);
+    }
+
+    #[cfg(feature = "engine")]
+    fn update_hint_search(&mut self) {
+        let search = match &self.hint_search {
+            Some(search) => search,
+            None => return,
+        };
+        if !self.is_live() || *search.state() != self.game.state {
+            self.hint_search = None;
+            return;
+        }
+        if let Some(result) = search.result() {
+            self.hint_search = None;
+            match result.best_move {
+                Some(mv) => self.give_hint(self.game.state.clone(), mv),
+                None => self.message = Some("There is no move to play.".to_owned()),
+            }
+        }
+    }
+
+    fn give_hint(&mut self, state: ChessState, mv: Move) {
+        self.game.count_hint(state.turn);
+        self.message = Some(format!("Hint: {}.", notation::san(&state, mv)));
+        self.hint = Some((state, mv));
+    }
+
+    // The hint for the position on the board, if it is still current
+    fn current_hint(&self) -> Option<Move> {
+        match &self.hint {
+            Some((state, mv)) if self.is_live() && *state == self.game.state => Some(*mv),
+            _ => None,
+        }
+    }
+
+    // The indices into the board fields of a cell on the screen
+    fn to_board(&self, x: u16, y: u16) -> (usize, usize) {
+        let (x, y) = if self.flipped { (7 - x, 7 - y) } else { (x, y) };
+        (x as usize, y as usize)
+    }
+
+    // Where a square is drawn on the board
+    fn to_screen(&self, pos: Pos) -> (u16, u16) {
+        let (x, y) = pos.to_ratatui();
+        if self.flipped { (7 - x, 7 - y) } else { (x, y) }
+    }
+
+    // Shows the position after the given number of half moves, the view
+    // ends at the current position
+    fn set_view(&mut self, view: Option<usize>) {
+        self.view = view.filter(|plies| *plies < self.game.state.history().len());
+        if self.view.is_some() {
+            self.selected = None;
+        }
+    }
+
+    // The position on the board, an earlier one while browsing the game
+    fn shown_state(&self) -> Cow<'_, ChessState> {
+        let plies = match self.view {
+            Some(plies) => plies,
+            None => return Cow::Borrowed(&self.game.state),
+        };
+        let mut chess_state = self.game.state.clone();
+        let len = chess_state.history().len();
+        for _ in plies..len {
+            chess_state.unmake_move();
+        }
+        Cow::Owned(chess_state)
+    }
+
+    // Moves and the cursor are only shown on the board of a running game
+    fn is_live(&self) -> bool {
+        self.view.is_none() && !self.game.is_finished()
+    }
+
+    fn first_key(&self, action: Action) -> String {
+        self.keymap.keys(action).split(", ").next().unwrap_or("").to_owned()
+    }
+
+    #[cfg(feature = "engine")]
+    fn toggle_analysis(&mut self) {
+        self.analysis = match self.analysis {
+            Some(_) => None,
+            None => Some(Analysis::new().threads(self.threads).weights(self.weights.clone())),
+        };
+    }
+
+    #[cfg(not(feature = "engine"))]
+    fn toggle_analysis(&mut self) {
+        self.message = Some("There is no engine in this build.".to_owned());
+    }
+
+    // The game in PGN, with the moves annotated once the review is done
+    pub fn pgn(&self) -> String {
+        #[cfg(feature = "engine")]
+        if let Some(review) = self.review.as_ref().and_then(Reviewer::review) {
+            return review.export(&self.game);
+        }
+        pgn::export(&self.game)
+    }
+
+    // Starts reviewing the game when it has ended, a move taken back ends
+    // the review
+    #[cfg(feature = "engine")]
+    fn update_review(&mut self) {
+        if !self.game.is_finished() || self.tutorial.is_some() || self.game.state.history().len() == 0 {
+            self.review = None;
+            return;
+        }
+        self.review
+            .get_or_insert_with(|| Reviewer::start(&self.game, review::DEFAULT_DEPTH, self.weights.clone()))
+            .poll();
+    }
+
+    // Runs the clock of the player to move, gives hints once they are
+    // found, lets the analysis follow the position on the board and the
+    // review follow the game
+    pub fn tick(&mut self, elapsed: Duration) {
+        self.game.tick(elapsed);
+        self.update_hint();
+        #[cfg(feature = "engine")]
+        self.update_review();
+        #[cfg(feature = "engine")]
+        if self.analysis.is_some() {
+            let shown = self.shown_state().into_owned();
+            if let Some(analysis) = &mut self.analysis {
+                analysis.analyze(&shown);
+            }
+        }
+    }
+
+    // Lets the engine think in the background while it is its turn and move
+    // once it is done, returns whether it moved. The engine thinks on its own
+    // time, which tick takes care of.
+    #[cfg(feature = "engine")]
+    pub fn engine_move(&mut self) -> bool {
+        let depth = match self.game.current_player() {
+            Player::Engine {depth} if !self.game.is_finished() => depth,
+            _ => {
+                self.stop_thinking();
+                return false;
+            },
+        };
+        if let Some(mv) = self.book.as_ref().and_then(|book| book.pick(&self.game.state)) {
+            self.thinking = None;
+            self.game.make_move(mv);
+            self.message = None;
+            return true;
+        }
+        if self.thinking.as_ref().map(Thinking::state) != Some(&self.game.state) {
+            self.thinking = Some(Thinking::start(&self.game.state, depth, self.threads, self.weights.clone()));
+        }
+        let result = match self.thinking.as_ref().and_then(Thinking::result) {
+            Some(result) => result,
+            None => return false,
+        };
+        self.thinking = None;
+        // take a draw when the position looks worse
+        let turn = self.game.state.turn;
+        if result.score < -50 && self.game.accept_draw(turn).is_ok() {
+            return true;
+        }
+        match result.best_move {
+            Some(mv) => {
+                self.game.make_move(mv);
+                self.message = None;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Gives up the engine's search, e.g. when the user quits or takes back
+    // a move
+    pub fn stop_thinking(&mut self) {
+        #[cfg(feature = "engine")]
+        {
+            self.thinking = None;
+        }
+    }
+
+    pub fn is_thinking(&self) -> bool {
+        #[cfg(feature = "engine")]
+        return self.thinking.is_some();
+        #[cfg(not(feature = "engine"))]
+        false
+    }
 }
 
 
@@ -62,64 +1018,748 @@ impl StatefulWidget for ChessWidget {
     type State = ChessWidgetState;
 
     fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
-        let chess_state = &mut state.chess_state;
         if area.left() + 8 > area.right() {
             return;
         }
         if area.top() + 8 > area.bottom() {
             return;
         }
-        if !chess_state.player_has_moves() {
-            let mut message: String = "".to_owned();
-            let color_s = match chess_state.turn {
-                state::Color::White => "White",
-                state::Color::Black => "Black",
-            };
-            if chess_state.player_is_in_check() {
-                message.push_str(color_s);
-                message.push_str(" lost!");
-            } else {
-                message.push_str("It's a draw!");
-            };
-            message.push_str(" Press 'q' to exit.");
-            for x in area.left()..area.right() {
-                for y in area.top()..area.bottom() {
-                    buf.get_mut(x, y).reset();
-                }
-            }
-            buf.set_string(0, 0, message, Style::default());
-            return;
+        let chess_state = state.shown_state();
+        match self.color_depth {
+            ColorDepth::Monochrome => self.render_board_monochrome(buf, state, &chess_state),
+            depth => self.render_board(buf, state, &chess_state, &self.theme.reduced(depth)),
+        }
+        self.render_status(area, buf, state, &chess_state);
+        #[cfg(feature = "engine")]
+        if let Some(analysis) = &mut state.analysis {
+            let evaluation = analysis.evaluation().cloned();
+            self.render_analysis(area, buf, evaluation.as_ref(), state.flipped);
+        } else if let Some(review) = &state.review {
+            self.render_review(area, buf, review);
+        } else {
+            self.render_book(area, buf, state);
         }
+        #[cfg(not(feature = "engine"))]
+        self.render_book(area, buf, state);
+        if state.show_help {
+            render_help(area, buf, &state.keymap);
+        }
+    }
+}
+
+impl ChessWidget {
+    fn render_board(&self, buf: &mut Buffer, state: &ChessWidgetState, chess_state: &ChessState, theme: &Theme) {
         for y in 0..8 {
             for x in 0..8 {
                 let bg = {
                     if (x+y) % 2 == 1 {
-                        style::Color::Rgb(184,139,74)
+                        theme.dark_square
                     } else {
-                        style::Color::Rgb(227,193,111)
+                        theme.light_square
                     }
                 };
+                let (file, rank) = state.to_board(x, y);
                 let cell = buf.get_mut(x, y);
                 cell.set_bg(bg);
-                if let Some(fig) = chess_state.board.fields[x as usize][y as usize] {
-                    let fg = match fig.color() {
-                        state::Color::White => style::Color::White,
-                        state::Color::Black => style::Color::Black,
-                    };
-                    cell.set_fg(fg);
-                    cell.set_symbol(fig.symbol());
+                match chess_state.board.fields[file][rank] {
+                    Some(fig) => {
+                        let fg = match fig.color() {
+                            state::Color::White => theme.white_figure,
+                            state::Color::Black => theme.black_figure,
+                        };
+                        cell.set_fg(fg);
+                        cell.set_symbol(&theme.pieces.symbol(fig));
+                    },
+                    None => {
+                        cell.set_symbol(theme.pieces.empty_symbol((x+y) % 2 == 1));
+                    },
                 }
             }
         }
-        let (cursor_x, cursor_y) = state.cursor.to_ratatui();
-        let cursor_color = match state.chess_state.turn {
-            state::Color::White => style::Color::Blue,
-            state::Color::Black => style::Color::Red,
+        if let Some(sel) = state.selected {
+            // mark where the selected figure may move to
+            for mv in chess_state.legal_moves_filtered(MoveFilter::From(sel)) {
+                let (x, y) = state.to_screen(mv.to_pos());
+                buf.get_mut(x, y).set_bg(theme.target);
+            }
+        }
+        if !state.is_live() {
+            // leave the final or earlier position as it is
+            return;
+        }
+        if let Some(hint) = state.current_hint() {
+            for pos in [hint.from_pos(), hint.to_pos()] {
+                let (x, y) = state.to_screen(pos);
+                buf.get_mut(x, y).set_bg(theme.hint);
+            }
+        }
+        let (cursor_x, cursor_y) = state.to_screen(state.cursor);
+        let cursor_color = match state.game.state.turn {
+            state::Color::White => theme.white_cursor,
+            state::Color::Black => theme.black_cursor,
         };
         buf.get_mut(cursor_x, cursor_y).set_bg(cursor_color);
         if let Some(sel) = state.selected {
-            let (sel_x, sel_y) = sel.to_ratatui();
-            buf.get_mut(sel_x, sel_y).set_bg(style::Color::Green);
+            let (sel_x, sel_y) = state.to_screen(sel);
+            buf.get_mut(sel_x, sel_y).set_bg(theme.selected);
+        }
+    }
+
+    // Without colours dark squares are drawn in reverse video and White's
+    // figures are told apart by their outline or upper case letter. Targets
+    // of the selected figure are marked with '*' or underlined, the cursor
+    // flips the square and shows '+' on empty squares.
+    fn render_board_monochrome(&self, buf: &mut Buffer, state: &ChessWidgetState, chess_state: &ChessState) {
+        for y in 0..8 {
+            for x in 0..8 {
+                let cell = buf.get_mut(x, y);
+                cell.set_style(Style::reset());
+                if (x+y) % 2 == 1 {
+                    cell.modifier = style::Modifier::REVERSED;
+                }
+                let (file, rank) = state.to_board(x, y);
+                let symbol = match chess_state.board.fields[file][rank] {
+                    Some(fig) => match (self.theme.pieces, fig.color()) {
+                        (PieceSet::Filled | PieceSet::Outline, state::Color::White) => PieceSet::Outline.symbol(fig),
+                        (PieceSet::Filled | PieceSet::Outline, state::Color::Black) => PieceSet::Filled.symbol(fig),
+                        (pieces, _) => pieces.symbol(fig),
+                    },
+                    None => " ".to_owned(),
+                };
+                cell.set_symbol(&symbol);
+            }
+        }
+        if let Some(sel) = state.selected {
+            for mv in chess_state.legal_moves_filtered(MoveFilter::From(sel)) {
+                let (x, y) = state.to_screen(mv.to_pos());
+                let cell = buf.get_mut(x, y);
+                if cell.symbol == " " {
+                    cell.set_symbol("*");
+                } else {
+                    cell.modifier |= style::Modifier::UNDERLINED;
+                }
+            }
+        }
+        if !state.is_live() {
+            return;
+        }
+        if let Some(hint) = state.current_hint() {
+            for pos in [hint.from_pos(), hint.to_pos()] {
+                let (x, y) = state.to_screen(pos);
+                buf.get_mut(x, y).modifier |= style::Modifier::SLOW_BLINK;
+            }
+        }
+        let (cursor_x, cursor_y) = state.to_screen(state.cursor);
+        let cell = buf.get_mut(cursor_x, cursor_y);
+        cell.modifier ^= style::Modifier::REVERSED;
+        if cell.symbol == " " {
+            cell.set_symbol("+");
+        }
+        if let Some(sel) = state.selected {
+            let (sel_x, sel_y) = state.to_screen(sel);
+            buf.get_mut(sel_x, sel_y).modifier |= style::Modifier::BOLD | style::Modifier::UNDERLINED;
+        }
+    }
+
+    // A bar beside the board filled with White's share of the evaluation,
+    // from White's side of the board, and the score and best line next to it
+    #[cfg(feature = "engine")]
+    fn render_analysis(&self, area: Rect, buf: &mut Buffer, evaluation: Option<&Evaluation>, flipped: bool) {
+        if area.width < 10 {
+            return;
+        }
+        let score = evaluation.map_or(0, |evaluation| evaluation.score);
+        let share = analysis::white_share(score);
+        // in eighths of a square, Black's share is drawn in reverse when
+        // Black is at the bottom
+        let (level, style) = match (flipped, self.color_depth) {
+            (false, ColorDepth::Monochrome) => ((share * 64.0).round() as i32, Style::default()),
+            (true, ColorDepth::Monochrome) =>
+                (((1.0 - share) * 64.0).round() as i32, Style::default().add_modifier(style::Modifier::REVERSED)),
+            (false, _) => ((share * 64.0).round() as i32, Style::default().fg(style::Color::White).bg(style::Color::Black)),
+            (true, _) => (((1.0 - share) * 64.0).round() as i32, Style::default().fg(style::Color::Black).bg(style::Color::White)),
+        };
+        const BLOCKS: [&str; 9] = [" ", "▁", "▂", "▃", "▄", "▅", "▆", "▇", "█"];
+        for row in 0..8 {
+            let fill = (level - row * 8).clamp(0, 8) as usize;
+            let cell = buf.get_mut(9, 7 - row as u16);
+            cell.set_style(Style::reset().patch(style));
+            cell.set_symbol(BLOCKS[fill]);
+        }
+
+        let width = area.width.saturating_sub(11) as usize;
+        let evaluation = match evaluation {
+            Some(evaluation) if width > 0 => evaluation,
+            _ => return,
+        };
+        let text = format!("{}  depth {}", analysis::format_score(evaluation.score), evaluation.depth);
+        buf.set_stringn(11, 0, text, width, Style::default().add_modifier(style::Modifier::BOLD));
+        // the best line, wrapped at the spaces
+        let mut lines = vec![String::new()];
+        for word in notation::san_line(&evaluation.state, &evaluation.pv).split(' ') {
+            let line = lines.last_mut().unwrap();
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(word.to_owned());
+            } else {
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(word);
+            }
+        }
+        for (y, line) in (1..8).zip(lines) {
+            buf.set_stringn(11, y, line, width, Style::default());
+        }
+    }
+
+    // The accuracy of both players and their worst moves beside the board
+    #[cfg(feature = "engine")]
+    fn render_review(&self, area: Rect, buf: &mut Buffer, reviewer: &Reviewer) {
+        let width = area.width.saturating_sub(9) as usize;
+        if width == 0 {
+            return;
+        }
+        let bold = Style::default().add_modifier(style::Modifier::BOLD);
+        let review = match reviewer.review() {
+            Some(review) => review,
+            None => {
+                let (searched, total) = reviewer.searched();
+                buf.set_stringn(9, 0, format!("Reviewing the game {}/{}", searched, total), width, bold);
+                return;
+            },
+        };
+        buf.set_stringn(9, 0, "Game review", width, bold);
+        for (y, color) in [(1, state::Color::White), (2, state::Color::Black)] {
+            let accuracy = review.accuracy(color).map_or("-".to_owned(), |accuracy| format!("{:.0}%", accuracy));
+            let text = format!("{} {}  ?! {}  ? {}  ?? {}",
+                               color_name(color),
+                               accuracy,
+                               review.count(color, review::Class::Inaccuracy),
+                               review.count(color, review::Class::Mistake),
+                               review.count(color, review::Class::Blunder));
+            buf.set_stringn(9, y, text, width, Style::default());
+        }
+        // the worst moves with the ones the engine preferred
+        let mut worst: Vec<(usize, &review::MoveReview)> = review.moves
+            .iter()
+            .enumerate()
+            .filter(|(_, mv)| mv.class.nag().is_some())
+            .collect();
+        worst.sort_by_key(|(i, mv)| (-mv.loss, *i));
+        worst.truncate(4);
+        worst.sort_by_key(|(i, _)| *i);
+        for (y, (_, mv)) in (4..8).zip(worst) {
+            let dots = if mv.color == state::Color::White { "." } else { "..." };
+            let mut text = format!("{}{} {}{}", mv.number, dots, mv.san, mv.class.symbol());
+            if let Some(best) = &mv.best_san {
+                text.push_str(&format!(" ({})", best));
+            }
+            buf.set_stringn(9, y, text, width, Style::default());
+        }
+    }
+
+    // The moves of the opening book for the position on the board with
+    // their share of the weights
+    fn render_book(&self, area: Rect, buf: &mut Buffer, state: &ChessWidgetState) {
+        let width = area.width.saturating_sub(9) as usize;
+        let chess_state = state.shown_state();
+        let moves = match &state.book {
+            Some(book) if width > 0 && state.tutorial.is_none() => book.moves(&chess_state),
+            _ => return,
+        };
+        if moves.is_empty() {
+            return;
+        }
+        let total: u32 = moves.iter().map(|(_, weight)| *weight as u32).sum();
+        buf.set_stringn(9, 0, "Book", width, Style::default().add_modifier(style::Modifier::BOLD));
+        for (y, (mv, weight)) in (1..8).zip(moves) {
+            let share = if total == 0 { 0.0 } else { weight as f64 * 100.0 / total as f64 };
+            let text = format!("{:<8}{:>3.0}%", notation::san(&chess_state, mv), share);
+            buf.set_stringn(9, y, text, width, Style::default());
+        }
+    }
+
+    // The lines under the board: whose turn it is with the clocks, or the
+    // result once the game has ended, then the last message and key hints
+    fn render_status(&self, area: Rect, buf: &mut Buffer, state: &ChessWidgetState, chess_state: &ChessState) {
+        let game = &state.game;
+        let width = area.width as usize;
+        let bold = Style::default().add_modifier(style::Modifier::BOLD);
+        let y = area.top() + 8;
+        if y >= area.bottom() {
+            return;
+        }
+        let outcome = game.outcome();
+        let lesson = state.tutorial.as_ref().map(|tutorial| (tutorial.number(), tutorial.lesson()));
+        match outcome {
+            _ if lesson.is_some() => {
+                let text = match lesson {
+                    Some((number, Some(lesson))) =>
+                        format!("{} ({}/{}): {}", lesson.title, number, tutorial::LESSONS.len(), lesson.task),
+                    _ => "Tutorial finished".to_owned(),
+                };
+                buf.set_stringn(0, y, text, width, bold);
+            },
+            Some(outcome) if state.view.is_none() => {
+                let text = format!("{} {}", pgn::result_tag(Some(outcome)), outcome);
+                buf.set_stringn(0, y, text, width, bold);
+            },
+            _ => {
+                let turn = chess_state.turn;
+                let text = format!("Move {}, {} to move", chess_state.fullmove_number, color_name(turn));
+                let (mut x, _) = buf.set_stringn(0, y, text, width, Style::default());
+                if chess_state.player_is_in_check() {
+                    let check = match self.color_depth {
+                        ColorDepth::Monochrome => bold,
+                        _ => bold.fg(style::Color::Red),
+                    };
+                    (x, _) = buf.set_stringn(x, y, " Check!", width.saturating_sub(x as usize), check);
+                }
+                if state.is_thinking() && state.view.is_none() {
+                    let dim = Style::default().add_modifier(style::Modifier::DIM);
+                    (x, _) = buf.set_stringn(x, y, " (thinking)", width.saturating_sub(x as usize), dim);
+                }
+                if let Some(clock) = &game.clock {
+                    for color in [state::Color::White, state::Color::Black] {
+                        let text = format!("  {} {}", color_name(color), clock::format_duration(clock.remaining(color)));
+                        let style = if color == game.state.turn { bold } else { Style::default() };
+                        (x, _) = buf.set_stringn(x, y, text, width.saturating_sub(x as usize), style);
+                    }
+                }
+                let hints = [state::Color::White, state::Color::Black].map(|color| game.hints(color));
+                if hints.iter().sum::<u32>() > 0 {
+                    let text = format!("  Hints {} {}", hints[0], hints[1]);
+                    buf.set_stringn(x, y, text, width.saturating_sub(x as usize), Style::default());
+                }
+            },
+        }
+
+        let y = y + 1;
+        if y >= area.bottom() {
+            return;
+        }
+        if let Some(text) = &state.prompt {
+            let (x, _) = buf.set_stringn(0, y, format!(":{}", text), width, Style::default());
+            if (x as usize) < width {
+                buf.get_mut(x, y).set_style(Style::default().add_modifier(style::Modifier::REVERSED));
+            }
+            let y = y + 1;
+            if y >= area.bottom() {
+                return;
+            }
+            match &state.prompt_error {
+                Some(error) => {
+                    let style = match self.color_depth {
+                        ColorDepth::Monochrome => bold,
+                        _ => bold.fg(style::Color::Red),
+                    };
+                    buf.set_stringn(0, y, error, width, style);
+                },
+                None => {
+                    let completions = prompt::completions(&game.state, text).join(" ");
+                    buf.set_stringn(0, y, completions, width, Style::default().add_modifier(style::Modifier::DIM));
+                },
+            }
+            return;
+        }
+        let message = match (&state.message, game.draw_offer()) {
+            _ if state.view.is_some() =>
+                Some(format!("Earlier position, press '{}' to return.", state.first_key(Action::GameEnd))),
+            (Some(message), _) => Some(message.clone()),
+            (None, Some(color)) =>
+                Some(format!("{} offers a draw, press '{}' to accept.", color_name(color), state.first_key(Action::Draw))),
+            (None, None) => None,
+        };
+        if let Some(message) = message {
+            buf.set_stringn(0, y, message, width, Style::default());
+        }
+
+        let y = y + 1;
+        if y >= area.bottom() {
+            return;
+        }
+        let hints = state.keymap.hints(outcome.is_some());
+        buf.set_stringn(0, y, hints, width, Style::default().add_modifier(style::Modifier::DIM));
+    }
+}
+
+// The key bindings in a box over the board
+fn render_help(area: Rect, buf: &mut Buffer, keymap: &Keymap) {
+    let command = keymap.keys(Action::Command).split(", ").next().unwrap_or("").to_owned();
+    let mut help = keymap.help();
+    help.extend(prompt::HELP.iter().map(|(usage, description)| (format!("{}{}", command, usage), *description)));
+    let width = help.iter().map(|(keys, _)| keys.chars().count()).max().unwrap_or(0);
+    let text = help
+        .iter()
+        .map(|(keys, description)| format!("{:width$}  {}", keys, description, width = width))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Clear.render(area, buf);
+    Paragraph::new(text)
+        .block(Block::default().borders(Borders::ALL).title("Keys"))
+        .render(area, buf);
+}
+
+fn opposite(dir: Dir) -> Dir {
+    match dir {
+        Dir::Left => Dir::Right,
+        Dir::Right => Dir::Left,
+        Dir::Up => Dir::Down,
+        Dir::Down => Dir::Up,
+        Dir::LeftUp => Dir::RightDown,
+        Dir::LeftDown => Dir::RightUp,
+        Dir::RightUp => Dir::LeftDown,
+        Dir::RightDown => Dir::LeftUp,
+    }
+}
+
+fn color_name(color: state::Color) -> &'static str {
+    match color {
+        state::Color::White => "White",
+        state::Color::Black => "Black",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(state: &mut ChessWidgetState) -> Buffer {
+        let area = Rect::new(0, 0, 40, 11);
+        let mut buf = Buffer::empty(area);
+        ChessWidget::new().render(area, &mut buf, state);
+        buf
+    }
+
+    fn line(buf: &Buffer, y: u16) -> String {
+        (0..buf.area.width).map(|x| buf.get(x, y).symbol.clone()).collect::<String>().trim_end().to_owned()
+    }
+
+    #[test]
+    fn color_depth() {
+        assert_eq!(ColorDepth::from_env(None, Some("truecolor"), Some("xterm-256color")), ColorDepth::TrueColor);
+        assert_eq!(ColorDepth::from_env(None, None, Some("xterm-256color")), ColorDepth::Ansi256);
+        assert_eq!(ColorDepth::from_env(None, None, Some("linux")), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::from_env(Some("1"), Some("truecolor"), None), ColorDepth::Monochrome);
+        assert_eq!(ColorDepth::from_env(None, None, Some("dumb")), ColorDepth::Monochrome);
+    }
+
+    #[test]
+    fn reduce_colors() {
+        let theme = Theme::classic().reduced(ColorDepth::Ansi256);
+        assert_eq!(theme.light_square, style::Color::Indexed(179));
+        assert_eq!(theme.white_figure, style::Color::White);
+        assert_eq!(reduce_color(style::Color::Rgb(250, 10, 10), ColorDepth::Ansi16), style::Color::LightRed);
+        assert_eq!(reduce_color(style::Color::Indexed(244), ColorDepth::Ansi16), style::Color::DarkGray);
+        assert_eq!(nearest_256((128, 128, 128)), 244);
+    }
+
+    #[test]
+    fn monochrome_board() {
+        let area = Rect::new(0, 0, 40, 11);
+        let mut buf = Buffer::empty(area);
+        let mut state = ChessWidgetState::new();
+        state.toggle_select();
+        ChessWidget::new().color_depth(ColorDepth::Monochrome).render(area, &mut buf, &mut state);
+        assert_eq!(line(&buf, 0), "♜♞♝♛♚♝♞♜");
+        assert_eq!(line(&buf, 7), "♖♘♗♕♔♗♘♖");
+        assert_eq!(line(&buf, 4), "    *");
+        assert_eq!(buf.get(1, 0).modifier, style::Modifier::REVERSED);
+        // the cursor is on the selected figure
+        assert_eq!(buf.get(4, 6).modifier, style::Modifier::BOLD | style::Modifier::UNDERLINED | style::Modifier::REVERSED);
+        assert_eq!(buf.get(0, 0).bg, style::Color::Reset);
+    }
+
+    #[test]
+    fn status_bar() {
+        let mut widget_state = ChessWidgetState::new();
+        let buf = render(&mut widget_state);
+        assert_eq!(line(&buf, 8), "Move 1, White to move");
+
+        let state = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut widget_state = ChessWidgetState::from_game(Game::new(state, Player::Human, Player::Human));
+        widget_state.game.move_checked("a1".parse().unwrap(), "a8".parse().unwrap()).unwrap();
+        let buf = render(&mut widget_state);
+        assert_eq!(line(&buf, 8), "1-0 White wins by checkmate");
+        assert_eq!(line(&buf, 10), ": command  ? help  q quit");
+        // the final position stays on the board
+        assert_eq!(buf.get(0, 0).symbol, "♜");
+    }
+
+    #[test]
+    fn browse_moves() {
+        let mut widget_state = ChessWidgetState::new();
+        for (from, to) in [("e2", "e4"), ("e7", "e5"), ("g1", "f3")] {
+            widget_state.game.move_checked(from.parse().unwrap(), to.parse().unwrap()).unwrap();
+        }
+        let key = |s: &str| s.parse::<Key>().unwrap();
+        assert_eq!(widget_state.press(key("g")), None);
+        assert_eq!(widget_state.press(key("g")), Some(Action::GameStart));
+        let buf = render(&mut widget_state);
+        assert_eq!(line(&buf, 4), "");
+        assert_eq!(line(&buf, 9), "Earlier position, press 'G' to return.");
+        widget_state.apply(Action::MoveForward);
+        widget_state.apply(Action::MoveForward);
+        let buf = render(&mut widget_state);
+        assert_eq!(line(&buf, 8), "Move 2, White to move");
+        assert!(buf.get(4, 3).symbol.starts_with('♟'));
+        assert_eq!(buf.get(6, 7).symbol, "♞");
+        // moving forward past the last move returns to the game
+        widget_state.apply(Action::MoveForward);
+        assert_eq!(widget_state.view, None);
+        widget_state.apply(Action::MoveBack);
+        assert_eq!(widget_state.view, Some(2));
+        widget_state.apply(Action::Select);
+        assert_eq!((widget_state.view, widget_state.selected), (None, None));
+
+        widget_state.apply(Action::Help);
+        let buf = render(&mut widget_state);
+        assert_eq!(line(&buf, 0), "┌Keys──────────────────────────────────┐");
+        assert!(line(&buf, 1).starts_with("│k                cursor up"));
+        let area = Rect::new(0, 0, 60, 30);
+        let mut buf = Buffer::empty(area);
+        ChessWidget::new().render(area, &mut buf, &mut widget_state);
+        assert_eq!(line(&buf, 20), "│:fen             show the position in FEN                 │");
+        widget_state.apply(Action::Resign);
+        assert!(!widget_state.show_help);
+        assert!(!widget_state.resign_pending);
+    }
+
+    #[test]
+    fn command_prompt() {
+        let mut widget_state = ChessWidgetState::new();
+        let type_text = |widget_state: &mut ChessWidgetState, text: &str| {
+            let mut action = None;
+            for c in text.chars() {
+                let key = match c {
+                    '\n' => Key::new(KeyCode::Enter),
+                    '\t' => Key::new(KeyCode::Tab),
+                    c => Key::new(KeyCode::Char(c)),
+                };
+                action = widget_state.press(key);
+            }
+            action
+        };
+        assert_eq!(type_text(&mut widget_state, ":Nf\t"), None);
+        let buf = render(&mut widget_state);
+        assert_eq!(line(&buf, 9), ":Nf3");
+        assert_eq!(line(&buf, 10), "Nf3");
+        assert_eq!(type_text(&mut widget_state, "\t\n"), Some(Action::Command));
+        assert_eq!(widget_state.game.state.to_fen(), "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1");
+
+        // errors are shown under the prompt, which stays open
+        type_text(&mut widget_state, ":e2e4\n");
+        let buf = render(&mut widget_state);
+        assert_eq!(line(&buf, 9), ":e2e4");
+        assert_eq!(widget_state.prompt_error.as_deref(), Some("illegal move 'e2e4': that is the opponents figure"));
+        assert!(line(&buf, 10).starts_with("illegal move 'e2e4'"));
+        widget_state.press(Key::new(KeyCode::Esc));
+        type_text(&mut widget_state, "/engine depth 3\n");
+        assert_eq!(widget_state.prompt_error.as_deref(), Some("there is no engine player"));
+        widget_state.press(Key::new(KeyCode::Esc));
+
+        type_text(&mut widget_state, ":d5\n:undo\n");
+        assert_eq!(widget_state.game.state.history().len(), 1);
+        type_text(&mut widget_state, ":flip\n");
+        let buf = render(&mut widget_state);
+        assert!(buf.get(4, 7).symbol.starts_with('♛'));
+        assert_eq!(buf.get(2, 2).symbol, "♞");
+        widget_state.apply(Action::Up);
+        assert_eq!(widget_state.cursor, "e1".parse().unwrap());
+        type_text(&mut widget_state, ":fen\n");
+        assert_eq!(widget_state.message.as_deref(), Some("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1"));
+    }
+
+    #[test]
+    fn tutorial() {
+        let mut widget_state = ChessWidgetState::tutorial();
+        let buf = render(&mut widget_state);
+        assert_eq!(line(&buf, 8), "The rook (1/10): Move the rook to a7.");
+        // a wrong move is taken back
+        widget_state.cursor = "a1".parse().unwrap();
+        widget_state.toggle_select();
+        widget_state.apply(Action::Right);
+        widget_state.toggle_select();
+        assert_eq!(widget_state.game.state.history().len(), 0);
+        assert_eq!(widget_state.message.as_deref(),
+                   Some("Not quite. Rooks move any number of squares up, down, left or right."));
+        widget_state.apply(Action::Command);
+        for c in "Ra7\n".chars() {
+            widget_state.press(Key::new(if c == '\n' { KeyCode::Enter } else { KeyCode::Char(c) }));
+        }
+        assert_eq!(widget_state.message.as_deref(), Some("Well done!"));
+        assert_eq!(widget_state.game.state.to_fen(), tutorial::LESSONS[1].fen);
+    }
+
+    #[cfg(feature = "engine")]
+    #[test]
+    fn evaluation_bar() {
+        let state = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut widget_state = ChessWidgetState::from_game(Game::new(state, Player::Human, Player::Human));
+        widget_state.apply(Action::Analyze);
+        let started = std::time::Instant::now();
+        let buf = loop {
+            widget_state.tick(Duration::ZERO);
+            let buf = render(&mut widget_state);
+            if line(&buf, 0).ends_with("#1  depth 2") {
+                break buf;
+            }
+            assert!(started.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert!(line(&buf, 1).ends_with(" █ 1. Ra8#"));
+        assert!((0..8).all(|y| buf.get(9, y).symbol == "█"));
+        widget_state.apply(Action::Analyze);
+        assert!(widget_state.analysis.is_none());
+    }
+
+    #[cfg(feature = "engine")]
+    #[test]
+    fn game_review() {
+        let mut widget_state = ChessWidgetState::new();
+        for (from, to) in [("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")] {
+            widget_state.game.move_checked(from.parse().unwrap(), to.parse().unwrap()).unwrap();
+        }
+        let started = std::time::Instant::now();
+        while widget_state.review.as_ref().and_then(Reviewer::review).is_none() {
+            widget_state.tick(Duration::ZERO);
+            assert!(started.elapsed() < Duration::from_secs(20));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let area = Rect::new(0, 0, 50, 11);
+        let mut buf = Buffer::empty(area);
+        ChessWidget::new().render(area, &mut buf, &mut widget_state);
+        assert!(line(&buf, 0).ends_with(" Game review"));
+        assert!(line(&buf, 1).ends_with(" White 50%  ?! 0  ? 0  ?? 1"));
+        assert!(line(&buf, 4).ends_with(" 2. g4?? (Nc3)"));
+        assert!(widget_state.pgn().contains("2. g4 $4 {[%eval #-1] Blunder."));
+        // taking back the mate ends the review
+        widget_state.apply(Action::Command);
+        for c in "undo\n".chars() {
+            widget_state.press(Key::new(if c == '\n' { KeyCode::Enter } else { KeyCode::Char(c) }));
+        }
+        widget_state.tick(Duration::ZERO);
+        assert!(widget_state.review.is_none());
+    }
+
+    // Ticks until the engine has given the hint
+    #[cfg(any(feature = "engine", unix))]
+    fn wait_for_hint(widget_state: &mut ChessWidgetState) {
+        let started = std::time::Instant::now();
+        while widget_state.message.as_deref() == Some("Looking for a hint...") {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+            widget_state.tick(Duration::ZERO);
+        }
+    }
+
+    #[cfg(feature = "engine")]
+    #[test]
+    fn hints() {
+        let state = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut widget_state = ChessWidgetState::from_game(Game::new(state, Player::Human, Player::Human));
+        widget_state.apply(Action::Hint);
+        // the engine searches in the background
+        assert_eq!(line(&render(&mut widget_state), 9), "Looking for a hint...");
+        assert_eq!(widget_state.game.hints(state::Color::White), 0);
+        wait_for_hint(&mut widget_state);
+        // asking again in the same position does not count
+        widget_state.apply(Action::Hint);
+        let buf = render(&mut widget_state);
+        assert_eq!(buf.get(0, 0).bg, Theme::classic().hint);
+        assert_eq!(buf.get(0, 7).bg, Theme::classic().hint);
+        assert_eq!(line(&buf, 8), "Move 1, White to move  Hints 1 0");
+        assert_eq!(line(&buf, 9), "Hint: Ra8#.");
+        widget_state.game.move_checked("g1".parse().unwrap(), "f1".parse().unwrap()).unwrap();
+        let buf = render(&mut widget_state);
+        assert_ne!(buf.get(0, 0).bg, Theme::classic().hint);
+        widget_state.apply(Action::Hint);
+        wait_for_hint(&mut widget_state);
+        assert_eq!(widget_state.game.hints(state::Color::Black), 1);
+        widget_state.game.black = Player::Engine {depth: 1};
+        widget_state.apply(Action::Hint);
+        assert_eq!(widget_state.message.as_deref(), Some("There is nothing to hint at."));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn external_hints() {
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg("while read line; do case \"$line\" in \
+                               uci) echo 'id name Fake'; echo uciok;; isready) echo readyok;; \
+                               go*) sleep 0.2; echo 'bestmove a1a8';; quit) exit;; esac; done");
+        let state = ChessState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut widget_state = ChessWidgetState::from_game(Game::new(state, Player::Human, Player::Human))
+            .hint_engine(ExternalEngine::start(command).unwrap());
+        // the engine thinks without holding up the user interface
+        let started = std::time::Instant::now();
+        widget_state.apply(Action::Hint);
+        widget_state.tick(Duration::ZERO);
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert_eq!(widget_state.message.as_deref(), Some("Looking for a hint..."));
+        // the hint for a position left is not given
+        widget_state.game.move_checked("g1".parse().unwrap(), "f1".parse().unwrap()).unwrap();
+        widget_state.tick(Duration::ZERO);
+        assert_eq!(widget_state.hint_engine.as_ref().unwrap().position(), None);
+        widget_state.game.undo().unwrap();
+        widget_state.apply(Action::Hint);
+        wait_for_hint(&mut widget_state);
+        assert_eq!(widget_state.message.as_deref(), Some("Hint: Ra8#."));
+        assert_eq!(widget_state.game.hints(state::Color::White), 1);
+    }
+
+    #[test]
+    fn book_moves() {
+        use crate::book::{self, Entry};
+        let after_e4 = ChessState::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        let entries = [
+            Entry {key: book::key(&ChessState::new()), mv: 0x31c, weight: 3, learn: 0},
+            Entry {key: book::key(&ChessState::new()), mv: 0x2db, weight: 1, learn: 0},
+            // c7c5
+            Entry {key: book::key(&after_e4), mv: 0xca2, weight: 1, learn: 0},
+        ];
+        let bytes: Vec<u8> = entries.iter().flat_map(Entry::to_bytes).collect();
+        let book = Book::parse(&bytes).unwrap();
+        let game = Game::new(ChessState::new(), Player::Human, Player::Engine {depth: 2});
+        let mut widget_state = ChessWidgetState::from_game(game).book(book);
+        let buf = render(&mut widget_state);
+        assert!(line(&buf, 0).ends_with(" Book"));
+        assert!(line(&buf, 1).ends_with(" e4       75%"));
+        assert_eq!(line(&buf, 2), "         d4       25%");
+        widget_state.game.move_checked("e2".parse().unwrap(), "e4".parse().unwrap()).unwrap();
+        // the engine plays from the book without thinking
+        #[cfg(feature = "engine")]
+        {
+            assert!(widget_state.engine_move());
+            assert_eq!(widget_state.game.state.history().last().unwrap().to_string(), "c7c5");
+            assert!(!line(&render(&mut widget_state), 0).contains("Book"));
+        }
+    }
+
+    #[cfg(feature = "engine")]
+    #[test]
+    fn engine_thinks_in_background() {
+        let game = Game::new(ChessState::new(), Player::Human, Player::Engine {depth: 2});
+        let mut widget_state = ChessWidgetState::from_game(game).threads(2);
+        widget_state.game.move_checked("e2".parse().unwrap(), "e4".parse().unwrap()).unwrap();
+        assert!(!widget_state.engine_move());
+        assert!(widget_state.is_thinking());
+        assert!(line(&render(&mut widget_state), 8).ends_with("Black to move (thinking)"));
+        // taking back the move gives up the search
+        widget_state.apply(Action::Command);
+        for c in "undo\n".chars() {
+            widget_state.press(Key::new(if c == '\n' { KeyCode::Enter } else { KeyCode::Char(c) }));
+        }
+        assert!(!widget_state.is_thinking());
+        assert!(!widget_state.engine_move());
+
+        widget_state.game.move_checked("d2".parse().unwrap(), "d4".parse().unwrap()).unwrap();
+        let started = std::time::Instant::now();
+        while !widget_state.engine_move() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
         }
+        assert_eq!(widget_state.game.state.history().len(), 2);
+        assert!(!widget_state.is_thinking());
     }
 }
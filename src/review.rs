@@ -0,0 +1,320 @@
+use crate::analysis;
+use crate::engine;
+use crate::eval::Weights;
+use crate::game::Game;
+use crate::notation;
+use crate::pgn;
+use crate::state::{Color, Move};
+use std::fmt;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+// The depth positions are searched to unless told otherwise
+pub const DEFAULT_DEPTH: u32 = 4;
+
+// Scores beyond this many centipawns, like mates, count as this much
+const SCORE_CAP: i32 = 1000;
+
+// How good a move was, by how much worse the position got compared to the
+// best move
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Class {
+    Best,
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Class {
+    // Classifies a move by the centipawns lost
+    pub fn from_loss(loss: i32) -> Self {
+        match loss {
+            ..=0 => Class::Best,
+            1..=49 => Class::Good,
+            50..=99 => Class::Inaccuracy,
+            100..=299 => Class::Mistake,
+            _ => Class::Blunder,
+        }
+    }
+
+    // The numeric annotation glyph in PGN, none for good moves
+    pub fn nag(&self) -> Option<u8> {
+        match self {
+            Class::Best | Class::Good => None,
+            Class::Inaccuracy => Some(6),
+            Class::Mistake => Some(2),
+            Class::Blunder => Some(4),
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Class::Best | Class::Good => "",
+            Class::Inaccuracy => "?!",
+            Class::Mistake => "?",
+            Class::Blunder => "??",
+        }
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Class::Best => "best",
+            Class::Good => "good",
+            Class::Inaccuracy => "inaccuracy",
+            Class::Mistake => "mistake",
+            Class::Blunder => "blunder",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MoveReview {
+    // the full move number
+    pub number: u32,
+    pub mv: Move,
+    pub san: String,
+    pub color: Color,
+    pub class: Class,
+    // centipawns lost compared to the best move
+    pub loss: i32,
+    // of the position after the move, in centipawns from White's point of view
+    pub score: i32,
+    // the move the engine prefers
+    pub best: Option<Move>,
+    pub best_san: Option<String>,
+}
+
+// The moves of a game checked by the engine
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Review {
+    pub moves: Vec<MoveReview>,
+}
+
+fn capped(score: i32) -> i32 {
+    score.clamp(-SCORE_CAP, SCORE_CAP)
+}
+
+// The chance to win in percent for a score in centipawns, fitted to games
+// between human players
+fn win_percent(score: i32) -> f64 {
+    50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * score as f64).exp()) - 1.0)
+}
+
+impl MoveReview {
+    // How close the move came to the best one in percent, by the chance to
+    // win it gave away
+    pub fn accuracy(&self) -> f64 {
+        let pov = |score: i32| match self.color {
+            Color::White => score,
+            Color::Black => -score,
+        };
+        let after = win_percent(pov(capped(self.score)));
+        let before = win_percent(pov(capped(self.score)) + self.loss);
+        (103.1668 * (-0.04354 * (before - after).max(0.0)).exp() - 3.1669).clamp(0.0, 100.0)
+    }
+}
+
+impl Review {
+    // The average accuracy of the player's moves, None if they made none
+    pub fn accuracy(&self, color: Color) -> Option<f64> {
+        let moves: Vec<f64> = self.moves.iter().filter(|mv| mv.color == color).map(MoveReview::accuracy).collect();
+        if moves.is_empty() {
+            return None;
+        }
+        Some(moves.iter().sum::<f64>() / moves.len() as f64)
+    }
+
+    pub fn count(&self, color: Color, class: Class) -> usize {
+        self.moves.iter().filter(|mv| mv.color == color && mv.class == class).count()
+    }
+
+    // The PGN annotation of each move: a NAG for bad moves, the evaluation
+    // and the better move
+    pub fn annotations(&self) -> Vec<String> {
+        self.moves
+            .iter()
+            .map(|mv| {
+                let mut annotation = String::new();
+                if let Some(nag) = mv.class.nag() {
+                    annotation.push_str(&format!("${} ", nag));
+                }
+                if mv.score.abs() == engine::MATE {
+                    // there is nothing to evaluate after a mate
+                    return annotation.trim_end().to_owned();
+                }
+                // %eval values carry no plus sign
+                let eval = analysis::format_score(mv.score);
+                annotation.push_str(&format!("{{[%eval {}]", eval.trim_start_matches('+')));
+                if let (Some(_), Some(best)) = (mv.class.nag(), &mv.best_san) {
+                    let class = mv.class.to_string();
+                    let class = class[..1].to_uppercase() + &class[1..];
+                    annotation.push_str(&format!(" {}. {} was best.", class, best));
+                }
+                annotation.push('}');
+                annotation
+            })
+            .collect()
+    }
+
+    pub fn export(&self, game: &Game) -> String {
+        pgn::export_annotated(game, &self.annotations())
+    }
+}
+
+// Searches every position of the game with the weights and classifies the
+// moves played. Progress is reported with the number of positions searched
+// and their total.
+pub fn review(game: &Game, depth: u32, weights: Arc<Weights>, progress: impl FnMut(usize, usize)) -> Review {
+    review_until(game, depth, weights, &|| false, progress).unwrap()
+}
+
+// Like review, but gives up once stop returns true, with None
+pub fn review_until(game: &Game,
+                    depth: u32,
+                    weights: Arc<Weights>,
+                    stop: &dyn Fn() -> bool,
+                    mut progress: impl FnMut(usize, usize)) -> Option<Review> {
+    let moves: Vec<Move> = game.state.history().collect();
+    let mut state = game.state.clone();
+    while state.unmake_move().is_some() {}
+
+    // scores from the point of view of the player to move
+    let total = moves.len() + 1;
+    let mut searched = vec![];
+    for i in 0..total {
+        progress(i, total);
+        let result = engine::search_until(&mut state.clone(), depth, weights.clone(), stop, |_| ());
+        if stop() {
+            return None;
+        }
+        searched.push((result.score, result.best_move));
+        if let Some(&mv) = moves.get(i) {
+            state.make_move(mv);
+        }
+    }
+    progress(total, total);
+
+    let mut state = game.state.clone();
+    while state.unmake_move().is_some() {}
+    let mut reviews = vec![];
+    for (i, &mv) in moves.iter().enumerate() {
+        let (before, best) = searched[i];
+        let after = -searched[i + 1].0;
+        let loss = if best == Some(mv) { 0 } else { (capped(before) - capped(after)).max(0) };
+        let color = state.turn;
+        let score = match color {
+            Color::White => after,
+            Color::Black => -after,
+        };
+        reviews.push(MoveReview {number: state.fullmove_number,
+                                 mv,
+                                 san: notation::san(&state, mv),
+                                 color,
+                                 class: Class::from_loss(loss),
+                                 loss,
+                                 score,
+                                 best,
+                                 best_san: best.map(|best| notation::san(&state, best))});
+        state.make_move(mv);
+    }
+    Some(Review {moves: reviews})
+}
+
+enum Progress {
+    Searched(usize, usize),
+    Done(Review),
+}
+
+// Reviews a game in a background thread. The review is given up when this
+// is dropped.
+pub struct Reviewer {
+    progress: Receiver<Progress>,
+    cancelled: Arc<AtomicBool>,
+    // positions searched and their total
+    searched: (usize, usize),
+    review: Option<Review>,
+}
+
+impl Reviewer {
+    pub fn start(game: &Game, depth: u32, weights: Arc<Weights>) -> Self {
+        let (sender, progress) = mpsc::channel();
+        let game = game.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let stop = cancelled.clone();
+        thread::spawn(move || {
+            let review = review_until(&game, depth, weights, &|| stop.load(Ordering::Relaxed), |searched, total| {
+                let _ = sender.send(Progress::Searched(searched, total));
+            });
+            if let Some(review) = review {
+                let _ = sender.send(Progress::Done(review));
+            }
+        });
+        Reviewer {progress, cancelled, searched: (0, 0), review: None}
+    }
+
+    // Takes in what the thread has done so far
+    pub fn poll(&mut self) {
+        while let Ok(progress) = self.progress.try_recv() {
+            match progress {
+                Progress::Searched(searched, total) => self.searched = (searched, total),
+                Progress::Done(review) => self.review = Some(review),
+            }
+        }
+    }
+
+    pub fn searched(&self) -> (usize, usize) {
+        self.searched
+    }
+
+    // The finished review
+    pub fn review(&self) -> Option<&Review> {
+        self.review.as_ref()
+    }
+}
+
+impl Drop for Reviewer {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Player;
+    use crate::state::ChessState;
+
+    #[test]
+    fn finds_blunder() {
+        // Black hangs the queen, White takes it
+        let state = ChessState::from_fen("3qk3/8/8/8/8/8/3R4/4K3 b - - 0 1").unwrap();
+        let mut game = Game::new(state, Player::Human, Player::Human);
+        for mv in ["Qd5", "Rxd5"] {
+            let mv = notation::parse_san(&game.state, mv).unwrap();
+            game.make_move(mv);
+        }
+        let mut calls = 0;
+        let review = review(&game, 3, Arc::new(Weights::default()), |_, _| calls += 1);
+        assert_eq!(calls, 4);
+        assert_eq!(review.moves[0].class, Class::Blunder);
+        assert_eq!(review.moves[1].class, Class::Best);
+        assert_eq!(review.count(Color::Black, Class::Blunder), 1);
+        assert!(review.accuracy(Color::White).unwrap() > 99.0);
+        assert!(review.accuracy(Color::Black).unwrap() < 20.0);
+
+        let pgn = review.export(&game);
+        assert!(pgn.contains("1... Qd5 $4 {[%eval 5.81] Blunder. "), "{}", pgn);
+        assert!(pgn.contains("2. Rxd5 {[%eval 5.65]} *"), "{}", pgn);
+        assert_eq!(pgn::import(&pgn).unwrap().state, game.state);
+        // a review given up stops after the position it is searching
+        let mut calls = 0;
+        assert!(review_until(&game, 3, Arc::new(Weights::default()), &|| true, |_, _| calls += 1).is_none());
+        assert_eq!(calls, 1);
+    }
+}
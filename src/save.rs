@@ -0,0 +1,125 @@
+use crate::game::Game;
+use crate::pgn;
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+// Where tress keeps its data, following the XDG base directory specification
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("tress"));
+    }
+    let home = env::var_os("HOME").filter(|home| !home.is_empty())?;
+    Some(PathBuf::from(home).join(".local").join("share").join("tress"))
+}
+
+fn games_dir() -> Result<PathBuf> {
+    data_dir()
+        .map(|dir| dir.join("games"))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "neither XDG_DATA_HOME nor HOME is set"))
+}
+
+// Ids name files in the games directory, so they must not lead out of it
+fn game_path(dir: &Path, id: &str) -> Result<PathBuf> {
+    if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("invalid game id '{}'", id)));
+    }
+    Ok(dir.join(format!("{}.json", id)))
+}
+
+// Write to a temporary file first, so that a crash never leaves a truncated
+// file behind
+fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(tmp_path, path)
+}
+
+// Writes the game to its save file, replacing an earlier save of it. The game
+// is also exported as PGN next to it.
+pub fn save(game: &Game) -> Result<()> {
+    save_in(&games_dir()?, game)
+}
+
+fn save_in(dir: &Path, game: &Game) -> Result<()> {
+    let path = game_path(dir, &game.id)?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    let json = serde_json::to_string(game).map_err(Error::other)?;
+    write_atomic(&path, &json)?;
+    write_atomic(&path.with_extension("pgn"), &pgn::export(game))
+}
+
+pub fn load(id: &str) -> Result<Game> {
+    load_from(&games_dir()?, id)
+}
+
+fn load_from(dir: &Path, id: &str) -> Result<Game> {
+    let json = fs::read_to_string(game_path(dir, id)?)?;
+    serde_json::from_str(&json).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+// All saved games that have not ended yet, the most recently saved first
+pub fn unfinished_games() -> Result<Vec<Game>> {
+    unfinished_games_in(&games_dir()?)
+}
+
+fn unfinished_games_in(dir: &Path) -> Result<Vec<Game>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut games = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let modified = fs::metadata(&path)?.modified()?;
+        // skip files that are not saves of tress, e.g. from older versions
+        let game: Game = match fs::read_to_string(&path).ok().and_then(|json| serde_json::from_str(&json).ok()) {
+            Some(game) => game,
+            None => continue,
+        };
+        if !game.is_finished() {
+            games.push((modified, game));
+        }
+    }
+    games.sort_by(|(a, _), (b, _)| b.cmp(a));
+    Ok(games.into_iter().map(|(_, game)| game).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Player;
+    use crate::state::ChessState;
+
+    #[test]
+    fn save_and_load() {
+        let dir = env::temp_dir().join(format!("tress-test-{}", std::process::id()));
+
+        let mut state = ChessState::new();
+        for (from, to) in [("e2", "e4"), ("e7", "e5"), ("g1", "f3")] {
+            state.move_checked(from.parse().unwrap(), to.parse().unwrap()).unwrap();
+        }
+        let game = Game::new(state, Player::Human, Player::Engine {depth: 4});
+        save_in(&dir, &game).unwrap();
+        let loaded = load_from(&dir, &game.id).unwrap();
+        assert_eq!(loaded, game);
+        assert_eq!(unfinished_games_in(&dir).unwrap(), vec![game.clone()]);
+        let pgn = fs::read_to_string(game_path(&dir, &game.id).unwrap().with_extension("pgn")).unwrap();
+        assert_eq!(pgn, pgn::export(&game));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ids_stay_in_the_directory() {
+        let dir = Path::new("games");
+        assert_eq!(game_path(dir, "1700000000").unwrap(), dir.join("1700000000.json"));
+        for id in ["", "..", "../../x", "a/b", "a\\b", ".hidden"] {
+            assert_eq!(game_path(dir, id).unwrap_err().kind(), ErrorKind::InvalidInput, "{}", id);
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use std::vec::Vec;
 use std::result::Result;
+use std::fmt;
+use std::str::FromStr;
+use crate::bitboard::{self, Bitboard, Squares};
+use crate::movelist::{MoveFilter, MoveList};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Color {
     White,
     Black
@@ -14,11 +21,19 @@ impl Color {
             Black => White,
         }
     }
+
+    pub(crate) fn index(&self) -> usize {
+        match self {
+            White => 0,
+            Black => 1,
+        }
+    }
 }
 
 use crate::state::Color::*;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Dir {
     Left,
     Right,
@@ -36,7 +51,7 @@ impl Dir {
             Left => vec![LeftUp, LeftDown],
             Right => vec![RightUp, RightDown],
             Up => vec![LeftUp, RightUp],
-            Down => vec![Left, RightDown],
+            Down => vec![LeftDown, RightDown],
             _ => panic!("undefined"),
         }
     }
@@ -44,15 +59,34 @@ impl Dir {
 
 use crate::state::Dir::*;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(try_from = "PosFields"))]
 pub struct Pos {
     pub file: usize,
     pub rank: usize,
 }
 
+// The serialized form of a Pos, checked to be on the board
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct PosFields {
+    file: usize,
+    rank: usize,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<PosFields> for Pos {
+    type Error = String;
+
+    fn try_from(fields: PosFields) -> Result<Self, String> {
+        Pos::new(fields.file, fields.rank)
+            .map_err(|_| format!("file {} and rank {} are not on the board", fields.file, fields.rank))
+    }
+}
+
 impl Pos {
     pub fn new(file: usize, rank: usize) -> Result<Pos, ()> {
-        if file <= 8 && file >= 1 && rank <= 8 && rank >= 1 {
+        if (1..=8).contains(&file) && (1..=8).contains(&rank) {
             Ok(Pos{file, rank})
         } else {
             Err(())
@@ -75,20 +109,50 @@ impl Pos {
     }
 
     pub fn move_dir(&self, dir: Dir) -> Result<Pos, ()> {
-        match dir {
-            Up => Pos::new(self.file, self.rank+1),
-            Down => Pos::new(self.file, self.rank-1),
-            Left => Pos::new(self.file-1, self.rank),
-            Right => Pos::new(self.file+1, self.rank),
-            LeftUp => Pos::new(self.file-1, self.rank+1),
-            LeftDown => Pos::new(self.file-1, self.rank-1),
-            RightUp => Pos::new(self.file+1, self.rank+1),
-            RightDown => Pos::new(self.file+1, self.rank-1),
+        let (file_delta, rank_delta) = match dir {
+            Up => (0, 1),
+            Down => (0, -1),
+            Left => (-1, 0),
+            Right => (1, 0),
+            LeftUp => (-1, 1),
+            LeftDown => (-1, -1),
+            RightUp => (1, 1),
+            RightDown => (1, -1),
+        };
+        // checked arithmetic, so that moving off the a-file or the 1st rank
+        // is an error instead of an underflow
+        let file = self.file.checked_add_signed(file_delta).ok_or(())?;
+        let rank = self.rank.checked_add_signed(rank_delta).ok_or(())?;
+        Pos::new(file, rank)
+    }
+}
+
+impl FromStr for Pos {
+    type Err = ();
+
+    // Parses a square in algebraic notation, e.g. "e4"
+    fn from_str(s: &str) -> Result<Pos, ()> {
+        let mut chars = s.chars();
+        let (file, rank) = match (chars.next(), chars.next(), chars.next()) {
+            (Some(file), Some(rank), None) => (file, rank),
+            _ => return Err(()),
+        };
+        if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+            return Err(());
         }
+        Pos::new(file as usize - 'a' as usize + 1, rank as usize - '0' as usize)
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file = char::from(b'a' + self.file as u8 - 1);
+        write!(f, "{}{}", file, self.rank)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Figure {
     Pawn(Color, bool),
     Rook(Color, bool),
@@ -100,6 +164,13 @@ pub enum Figure {
 
 use crate::state::Figure::*;
 
+pub(crate) const PAWN: usize = 0;
+pub(crate) const ROOK: usize = 1;
+pub(crate) const KNIGHT: usize = 2;
+pub(crate) const BISHOP: usize = 3;
+pub(crate) const QUEEN: usize = 4;
+pub(crate) const KING: usize = 5;
+
 impl Figure {
     pub fn symbol(&self) -> &str {
         match self {
@@ -144,9 +215,51 @@ impl Figure {
             King(_,m) => *m = val,
         }
     }
+
+    // Index of the kind of figure into Board::pieces
+    pub(crate) fn kind_index(&self) -> usize {
+        match self {
+            Pawn(_,_) => PAWN,
+            Rook(_,_) => ROOK,
+            Knight(_,_) => KNIGHT,
+            Bishop(_,_) => BISHOP,
+            Queen(_,_) => QUEEN,
+            King(_,_) => KING,
+        }
+    }
+
+    // The letter used for the figure in FEN, upper case for White
+    pub fn letter(&self) -> char {
+        let letter = match self {
+            Pawn(_,_) => 'p',
+            Rook(_,_) => 'r',
+            Knight(_,_) => 'n',
+            Bishop(_,_) => 'b',
+            Queen(_,_) => 'q',
+            King(_,_) => 'k',
+        };
+        match self.color() {
+            White => letter.to_ascii_uppercase(),
+            Black => letter,
+        }
+    }
+
+    pub fn from_letter(letter: char, has_moved: bool) -> Result<Figure, ()> {
+        let color = if letter.is_ascii_uppercase() { White } else { Black };
+        match letter.to_ascii_lowercase() {
+            'p' => Ok(Pawn(color, has_moved)),
+            'r' => Ok(Rook(color, has_moved)),
+            'n' => Ok(Knight(color, has_moved)),
+            'b' => Ok(Bishop(color, has_moved)),
+            'q' => Ok(Queen(color, has_moved)),
+            'k' => Ok(King(color, has_moved)),
+            _ => Err(()),
+        }
+    }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Move {
     // All checking whether a move is possible is not done here.
     // A Normal move means that we just move a single figure, not taking
@@ -163,7 +276,12 @@ pub enum Move {
     Castle(Pos, Pos, Pos, Pos),
     // The Promotion of a Pawn to a new Figure.
     // Associated values: old position Pawn, new position, Figure replacing the Pawn
-    Promote(Pos, Pos, Figure)
+    // and the opponents figure taken on the new position, if any
+    Promote(Pos, Pos, Figure, Option<Figure>),
+    // Taking a Pawn en passant.
+    // Associated values: old position, new position and the position of the taken Pawn.
+    // The moving Pawn has always moved before.
+    EnPassant(Pos, Pos, Pos)
 }
 
 impl Move {
@@ -172,7 +290,8 @@ impl Move {
             Normal(p,_,_) => *p,
             Take(p,_,_,_) => *p,
             Castle(p,_,_,_) => *p,
-            Promote(p,_,_) => *p,
+            Promote(p,_,_,_) => *p,
+            EnPassant(p,_,_) => *p,
         }
     }
 
@@ -181,7 +300,8 @@ impl Move {
             Normal(_,p,_) => *p,
             Take(_,p,_,_) => *p,
             Castle(_,_,p,_) => *p,
-            Promote(_,p,_) => *p,
+            Promote(_,p,_,_) => *p,
+            EnPassant(_,p,_) => *p,
         }
     }
 
@@ -190,21 +310,156 @@ impl Move {
             Normal(_,_,m) => *m,
             Take(_,_,_,m) => *m,
             Castle(_,_,_,_) => false,
-            Promote(_,_,_) => true,
+            Promote(_,_,_,_) => true,
+            EnPassant(_,_,_) => true,
+        }
+    }
+
+    pub fn is_capture(&self) -> bool {
+        matches!(self, Take(_,_,_,_) | Promote(_,_,_,Some(_)) | EnPassant(_,_,_))
+    }
+
+    // The opponents figure taken by this move. Figures taken en passant are
+    // always Pawns, but we do not know their has_moved value here.
+    pub fn taken(&self) -> Option<Figure> {
+        match self {
+            Take(_,_,fig,_) => Some(*fig),
+            Promote(_,_,_,fig) => *fig,
+            _ => None,
         }
     }
 }
 
+impl fmt::Display for Move {
+    // Formats the move in UCI notation, e.g. "e2e4" or "e7e8q"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.from_pos(), self.to_pos())?;
+        if let Promote(_,_,fig,_) = self {
+            write!(f, "{}", fig.letter().to_ascii_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
 use crate::state::Move::*;
 
+// Why a move was rejected by move_checked
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MoveError {
+    GameOver,
+    NoFigure,
+    OpponentsFigure,
+    // the figure does not move like that, or something is in the way
+    Unreachable,
+    OccupiedByOwnFigure,
+    // Pawns can only become Queens, Rooks, Bishops or Knights
+    InvalidPromotion,
+    // the move does not get the King out of check
+    InCheck,
+    // the move would put the King in check, e.g. by moving a pinned figure
+    LeavesKingInCheck,
+    // the King or the Rook has moved already
+    CastlingRightsLost,
+    CastlingBlocked,
+    CastlingOutOfCheck,
+    CastlingThroughCheck,
+    CastlingIntoCheck,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            MoveError::GameOver => "the game is over",
+            MoveError::NoFigure => "there is no figure on that square",
+            MoveError::OpponentsFigure => "that is the opponents figure",
+            MoveError::Unreachable => "the figure cannot move there",
+            MoveError::OccupiedByOwnFigure => "the square is taken by an own figure",
+            MoveError::InvalidPromotion => "a Pawn cannot become that figure",
+            MoveError::InCheck => "the King is in check",
+            MoveError::LeavesKingInCheck => "the King would be in check",
+            MoveError::CastlingRightsLost => "the King or the Rook has moved already",
+            MoveError::CastlingBlocked => "there are figures between the King and the Rook",
+            MoveError::CastlingOutOfCheck => "the King cannot castle out of check",
+            MoveError::CastlingThroughCheck => "the King cannot castle through an attacked square",
+            MoveError::CastlingIntoCheck => "the King cannot castle into check",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize),
+           serde(try_from = "BoardFields", into = "BoardFields"))]
 pub struct Board {
-    pub fields: [[Option<Figure>; 8]; 8]
+    pub fields: [[Option<Figure>; 8]; 8],
+    // The same position as bitboards, one per kind of figure and one per
+    // color, kept in sync by add_figure and remove_figure
+    pieces: [Bitboard; 6],
+    colors: [Bitboard; 2],
+}
+
+// The serialized form of a Board, the bitboards are rebuilt from the fields
+// and the position is checked with Board::validate
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct BoardFields {
+    fields: [[Option<Figure>; 8]; 8],
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<BoardFields> for Board {
+    type Error = String;
+
+    fn try_from(board_fields: BoardFields) -> Result<Self, String> {
+        let mut board = Board::empty();
+        for file in 1..=8 {
+            for rank in 1..=8 {
+                let pos = Pos::new(file, rank).unwrap();
+                let (x, y) = pos.to_array();
+                if let Some(figure) = board_fields.fields[x][y] {
+                    board.add_figure(pos, figure);
+                }
+            }
+        }
+        board.validate()?;
+        Ok(board)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Board> for BoardFields {
+    fn from(board: Board) -> Self {
+        BoardFields {fields: board.fields}
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Draws the board as text with FEN letters, White at the bottom
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for rank in (1..=8).rev() {
+            write!(f, "{} ", rank)?;
+            for file in 1..=8 {
+                let letter = self.fields_get(Pos::new(file, rank).unwrap()).map_or('.', |fig| fig.letter());
+                write!(f, " {}", letter)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "   a b c d e f g h")
+    }
 }
 
 impl Board {
     pub fn new() -> Self {
-        let fields: [[Option<Figure>; 8]; 8] = [[None; 8]; 8];
-        let mut board = Board {fields};
+        let mut board = Board::empty();
         // Pawns
         for r in 1..=8 {
             board.add_figure(Pos::new(r, 2).unwrap(), Pawn(White, false));
@@ -234,6 +489,13 @@ impl Board {
         board
     }
 
+    pub fn empty() -> Self {
+        let fields: [[Option<Figure>; 8]; 8] = [[None; 8]; 8];
+        Board {fields, pieces: [0; 6], colors: [0; 2]}
+    }
+
+    // Changing the kind or color of the figure through this reference does
+    // not update the bitboards, use add_figure and remove_figure for that
     pub fn fields_get_mut(&mut self, pos: Pos) -> &mut Option<Figure>{
         let (x, y) = pos.to_array();
         &mut self.fields[x][y]
@@ -245,11 +507,98 @@ impl Board {
     }
 
     pub fn add_figure(&mut self, pos: Pos, figure: Figure) {
+        // a figure already standing on the square is replaced
+        self.remove_figure(pos);
+        let bit = bitboard::bit(bitboard::square(pos));
+        self.pieces[figure.kind_index()] |= bit;
+        self.colors[figure.color().index()] |= bit;
         *self.fields_get_mut(pos) = Some(figure);
     }
 
     pub fn remove_figure(&mut self, pos: Pos) -> Option<Figure> {
-        self.fields_get_mut(pos).take()
+        let figure = self.fields_get_mut(pos).take()?;
+        let bit = bitboard::bit(bitboard::square(pos));
+        self.pieces[figure.kind_index()] &= !bit;
+        self.colors[figure.color().index()] &= !bit;
+        Some(figure)
+    }
+
+    // Checks that the position could come from a game: one King and at
+    // most 16 figures with at most 8 Pawns per side, and no Pawns on the
+    // first or last rank. Move generation relies on this.
+    pub fn validate(&self) -> Result<(), String> {
+        for color in [White, Black] {
+            let name = match color {
+                White => "White",
+                Black => "Black",
+            };
+            if self.pieces(KING, color).count_ones() != 1 {
+                return Err(format!("{} needs exactly one King", name));
+            }
+            if self.color_pieces(color).count_ones() > 16 {
+                return Err(format!("{} has more than 16 figures", name));
+            }
+            if self.pieces(PAWN, color).count_ones() > 8 {
+                return Err(format!("{} has more than 8 Pawns", name));
+            }
+        }
+        // the first and the last rank
+        if self.pieces[PAWN] & 0xFF00_0000_0000_00FF != 0 {
+            return Err("Pawns cannot stand on the first or last rank".to_owned());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn pieces(&self, kind: usize, color: Color) -> Bitboard {
+        self.pieces[kind] & self.colors[color.index()]
+    }
+
+    pub(crate) fn color_pieces(&self, color: Color) -> Bitboard {
+        self.colors[color.index()]
+    }
+
+    pub(crate) fn occupied(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
+    pub(crate) fn king_square(&self, color: Color) -> Option<usize> {
+        Squares(self.pieces(KING, color)).next()
+    }
+
+    // All figures of the given color attacking the square, with sliding
+    // figures blocked by the given occupancy
+    pub(crate) fn attackers(&self, square: usize, color: Color, occupied: Bitboard) -> Bitboard {
+        let diagonal = self.pieces[BISHOP] | self.pieces[QUEEN];
+        let straight = self.pieces[ROOK] | self.pieces[QUEEN];
+        let attackers = (bitboard::pawn_attacks(color.other(), square) & self.pieces[PAWN])
+            | (bitboard::knight_attacks(square) & self.pieces[KNIGHT])
+            | (bitboard::king_attacks(square) & self.pieces[KING])
+            | (bitboard::bishop_attacks(square, occupied) & diagonal)
+            | (bitboard::rook_attacks(square, occupied) & straight);
+        attackers & self.colors[color.index()]
+    }
+
+    // Figures of the given color that are the only figure between their King
+    // and an enemy sliding figure
+    pub(crate) fn pinned(&self, color: Color) -> Bitboard {
+        let king = match self.king_square(color) {
+            Some(king) => king,
+            None => return 0,
+        };
+        let enemy = color.other();
+        let diagonal = self.pieces(BISHOP, enemy) | self.pieces(QUEEN, enemy);
+        let straight = self.pieces(ROOK, enemy) | self.pieces(QUEEN, enemy);
+        // enemy sliders that would attack the King on an empty board
+        let snipers = (bitboard::bishop_attacks(king, 0) & diagonal)
+            | (bitboard::rook_attacks(king, 0) & straight);
+        let mut pinned = 0;
+        for sniper in Squares(snipers) {
+            let blockers = bitboard::between(king, sniper) & self.occupied();
+            if blockers.count_ones() == 1 {
+                pinned |= blockers & self.color_pieces(color);
+            }
+        }
+        pinned
     }
 
     pub fn move_figure(&mut self,
@@ -270,10 +619,14 @@ impl Board {
                 self.move_figure(k_old_pos, k_new_pos, true);
                 self.move_figure(r_old_pos, r_new_pos, true);
             },
-            Promote(old_pos, new_pos, figure) => {
+            Promote(old_pos, new_pos, figure, _) => {
                 self.remove_figure(old_pos);
                 self.add_figure(new_pos, figure);
             },
+            EnPassant(old_pos, new_pos, taken_pos) => {
+                self.move_figure(old_pos, new_pos, true);
+                self.remove_figure(taken_pos);
+            },
         }
     }
 
@@ -288,185 +641,926 @@ impl Board {
                 self.move_figure(k_new_pos, k_old_pos, false);
                 self.move_figure(r_new_pos, r_old_pos, false);
             },
-            Promote(old_pos, new_pos, figure) => {
+            Promote(old_pos, new_pos, figure, taken) => {
                 self.remove_figure(new_pos);
                 self.add_figure(old_pos, Pawn(figure.color(), true));
+                if let Some(taken) = taken {
+                    self.add_figure(new_pos, taken);
+                }
+            },
+            EnPassant(old_pos, new_pos, taken_pos) => {
+                let color = self.fields_get(new_pos).unwrap().color();
+                self.move_figure(new_pos, old_pos, true);
+                self.add_figure(taken_pos, Pawn(color.other(), true));
             },
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PositionKey {
+    pieces: [Bitboard; 6],
+    colors: [Bitboard; 2],
+    turn: Color,
+    castling_rights: [bool; 4],
+    en_passant: Option<Pos>,
+}
+
+// Everything needed to take back a move in ChessState::unmake_move
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct HistoryEntry {
+    mv: Move,
+    en_passant: Option<Pos>,
+    halfmove_clock: u32,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize),
+           serde(try_from = "ChessStateFields", into = "ChessStateFields"))]
 pub struct ChessState {
     pub board: Board,
     pub turn: Color,
     pub mate: bool,
+    // The square a Pawn skipped over with its last move, if it moved two squares
+    pub en_passant: Option<Pos>,
+    // Number of half moves since the last capture or Pawn move
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    history: Vec<HistoryEntry>,
+}
+
+// The serialized form of a ChessState. The moves of the history have to be
+// legal and lead to the position, mate is found again instead of read.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct ChessStateFields {
+    board: Board,
+    turn: Color,
+    mate: bool,
+    en_passant: Option<Pos>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    history: Vec<HistoryEntry>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ChessStateFields> for ChessState {
+    type Error = String;
+
+    fn try_from(fields: ChessStateFields) -> Result<Self, String> {
+        let mut state = ChessState {board: fields.board,
+                                    turn: fields.turn,
+                                    mate: false,
+                                    en_passant: fields.en_passant,
+                                    halfmove_clock: fields.halfmove_clock,
+                                    fullmove_number: fields.fullmove_number,
+                                    history: vec![]};
+        state.validate_en_passant()?;
+        let position = state.clone();
+        let invalid = || "the moves played do not lead to the position".to_owned();
+        // take the moves back, each has to have left its figures where it
+        // went, and play them again from the start
+        for &entry in fields.history.iter().rev() {
+            let mover = state.turn.other();
+            let landed = match entry.mv {
+                Castle(_, _, king, rook) if king == rook => return Err(invalid()),
+                Castle(_, _, king, rook) => vec![king, rook],
+                mv => vec![mv.to_pos()],
+            };
+            if landed.iter().any(|&pos| state.board.fields_get(pos).map(|fig| fig.color()) != Some(mover))
+                || (mover == Black && state.fullmove_number <= 1) {
+                return Err(invalid());
+            }
+            state.history.push(entry);
+            state.unmake_move();
+        }
+        state.board.validate()?;
+        state.validate_en_passant()?;
+        for entry in fields.history {
+            if entry.en_passant != state.en_passant
+                || entry.halfmove_clock != state.halfmove_clock
+                || !state.legal_moves().contains(&entry.mv) {
+                return Err(invalid());
+            }
+            state.make_move(entry.mv);
+        }
+        if state.board != position.board
+            || state.en_passant != position.en_passant
+            || state.halfmove_clock != position.halfmove_clock
+            || state.fullmove_number != position.fullmove_number {
+            return Err(invalid());
+        }
+        state.mate = state.player_is_in_check() && !state.player_has_moves();
+        Ok(state)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ChessState> for ChessStateFields {
+    fn from(state: ChessState) -> Self {
+        ChessStateFields {board: state.board,
+                          turn: state.turn,
+                          mate: state.mate,
+                          en_passant: state.en_passant,
+                          halfmove_clock: state.halfmove_clock,
+                          fullmove_number: state.fullmove_number,
+                          history: state.history}
+    }
+}
+
+impl Default for ChessState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ChessState {
     pub fn new() -> Self {
-        ChessState{board: Board::new(), turn: White, mate: false}
+        ChessState{board: Board::new(),
+                   turn: White,
+                   mate: false,
+                   en_passant: None,
+                   halfmove_clock: 0,
+                   fullmove_number: 1,
+                   history: vec![]}
     }
 
-    pub fn player_is_in_check(&mut self) -> bool {
-        self.check_for_mate(self.turn)
-    }
+    // Sets up a position from Forsyth-Edwards Notation.
+    // Castling rights are mapped to the has_moved values of Kings and Rooks.
+    // Positions that could not come from a game are rejected, see
+    // Board::validate, as are en passant squares no Pawn skipped.
+    pub fn from_fen(fen: &str) -> Result<Self, ()> {
+        let mut parts = fen.split_whitespace();
+        let placement = parts.next().ok_or(())?;
+        let turn = parts.next().unwrap_or("w");
+        let castling = parts.next().unwrap_or("-");
+        let en_passant = parts.next().unwrap_or("-");
+        let halfmove_clock = parts.next().unwrap_or("0");
+        let fullmove_number = parts.next().unwrap_or("1");
 
-    pub fn player_has_moves(&mut self) -> bool {
-        for file in 1..=8 {
-            for rank in 1..=8 {
-                let pos = Pos::new(file, rank).unwrap();
-                if let Some(figure) = self.board.fields_get(pos) {
-                    if figure.color() == self.turn
-                        && self.possible_moves(pos, true).len() != 0 {
-                        return true;
-                    }
+        let mut board = Board::empty();
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(());
+        }
+        for (i, rank_s) in ranks.iter().enumerate() {
+            let rank = 8 - i;
+            let mut file = 1;
+            for c in rank_s.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    file += empty as usize;
+                    continue;
                 }
+                let pos = Pos::new(file, rank)?;
+                let figure = Figure::from_letter(c, true)?;
+                // Pawns on their starting rank may still move two squares
+                let has_moved = match figure {
+                    Pawn(White, _) => rank != 2,
+                    Pawn(Black, _) => rank != 7,
+                    _ => true,
+                };
+                let mut figure = figure;
+                figure.set_moved_to(has_moved);
+                board.add_figure(pos, figure);
+                file += 1;
+            }
+            if file != 9 {
+                return Err(());
             }
         }
-        false
-    }
+        // also keeps the number of moves within a MoveList
+        board.validate().map_err(|_| ())?;
 
-    fn check_for_mate(&mut self, color: Color) -> bool {
-        let enemy = color.other();
-        for file in 1..=8 {
-            for rank in 1..=8 {
-                let pos = Pos::new(file, rank).unwrap();
-                if let Some(figure) = self.board.fields_get(pos) {
-                    if figure.color() == enemy {
-                        if self.possible_moves(pos, false)
-                            .iter()
-                            .position(|mv| {
-                                if let Take(_, _, attacked_fig, _) = mv {
-                                    return matches!(attacked_fig, King(_, _));
-                                }
-                                false
-                            })
-                            .is_some() {
-                            return true;
+        if castling != "-" {
+            for c in castling.chars() {
+                let (color, rook_file) = match c {
+                    'K' => (White, 8),
+                    'Q' => (White, 1),
+                    'k' => (Black, 8),
+                    'q' => (Black, 1),
+                    _ => return Err(()),
+                };
+                let rank = match color {
+                    White => 1,
+                    Black => 8,
+                };
+                for pos in [Pos::new(5, rank)?, Pos::new(rook_file, rank)?] {
+                    if let Some(fig) = board.fields_get_mut(pos) {
+                        if fig.color() == color {
+                            fig.set_moved_to(false);
                         }
                     }
                 }
             }
         }
-        false
+
+        let turn = match turn {
+            "w" => White,
+            "b" => Black,
+            _ => return Err(()),
+        };
+        let en_passant = match en_passant {
+            "-" => None,
+            s => Some(s.parse()?),
+        };
+        let state = ChessState{board,
+                               turn,
+                               mate: false,
+                               en_passant,
+                               halfmove_clock: halfmove_clock.parse().map_err(|_| ())?,
+                               fullmove_number: fullmove_number.parse().map_err(|_| ())?,
+                               history: vec![]};
+        state.validate_en_passant().map_err(|_| ())?;
+        Ok(state)
     }
 
-    fn check_move_for_mate(&mut self, mv: Move) -> bool {
-        // temporarily move the piece(s)...
-        self.board.do_move(mv);
-        // ...to check if there would be a check mate...
-        let result = self.check_for_mate(self.turn);
-        // ...and move the piece(s) back
-        self.board.undo_move(mv);
-        !result
-    }
-
-    fn possible_moves(&mut self, pos: Pos, check_for_mate: bool) -> Vec<Move> {
-        let mut moves = vec![];
-        let figure = self.board.fields_get(pos).unwrap();
-        let color = figure.color();
-        let has_moved = figure.has_moved();
-        let mut push_moves_line = |dirs: Vec<Dir>, single: bool| {
-                let mut cur_pos = pos;
-                for dir in dirs {
-                    while let Ok(next_pos) = cur_pos.move_dir(dir) {
-                        match self.board.fields_get(next_pos) {
-                            Some(fig) => {
-                                if fig.color() != color {
-                                    moves.push(Take(pos, next_pos, fig, has_moved));
-                                }
-                                break;
-                            },
-                            None => moves.push(Normal(pos, next_pos, has_moved)),
-                        };
-                        if single {
-                            break;
+    // Checks that the en passant square, if any, is the one just skipped by
+    // a Pawn of the player who moved last
+    fn validate_en_passant(&self) -> Result<(), String> {
+        let skipped = match self.en_passant {
+            Some(skipped) => skipped,
+            None => return Ok(()),
+        };
+        let (rank, forward): (isize, isize) = match self.turn {
+            White => (6, -1),
+            Black => (3, 1),
+        };
+        let mover = self.turn.other();
+        let square = |rank: isize| Pos::new(skipped.file, rank as usize).map(|pos| self.board.fields_get(pos));
+        let pawn = matches!(square(rank + forward), Ok(Some(Pawn(color, _))) if color == mover);
+        if skipped.rank != rank as usize || !pawn || square(rank) != Ok(None) || square(rank - forward) != Ok(None) {
+            return Err(format!("{} is not an en passant square", skipped));
+        }
+        Ok(())
+    }
+
+    // Castling rights in FEN order: White short and long, Black short and long.
+    // A right only means that King and Rook have not moved yet.
+    pub fn castling_rights(&self) -> [bool; 4] {
+        let mut rights = [false; 4];
+        for (i, (color, rook_file)) in [(White, 8), (White, 1), (Black, 8), (Black, 1)].into_iter().enumerate() {
+            let rank = match color {
+                White => 1,
+                Black => 8,
+            };
+            let king = self.board.fields_get(Pos::new(5, rank).unwrap());
+            let rook = self.board.fields_get(Pos::new(rook_file, rank).unwrap());
+            rights[i] = king == Some(King(color, false)) && rook == Some(Rook(color, false));
+        }
+        rights
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for rank in (1..=8).rev() {
+            let mut empty = 0;
+            for file in 1..=8 {
+                match self.board.fields_get(Pos::new(file, rank).unwrap()) {
+                    Some(figure) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
                         }
-                        cur_pos = next_pos;
-                    }
-                    cur_pos = pos;
+                        fen.push(figure.letter());
+                    },
+                    None => empty += 1,
                 }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank > 1 {
+                fen.push('/');
+            }
+        }
+        fen.push_str(match self.turn {
+            White => " w ",
+            Black => " b ",
+        });
+        let castling: String = self.castling_rights()
+            .iter()
+            .zip("KQkq".chars())
+            .filter(|(right, _)| **right)
+            .map(|(_, c)| c)
+            .collect();
+        fen.push_str(if castling.is_empty() { "-" } else { &castling });
+        match self.en_passant {
+            Some(pos) => fen.push_str(&format!(" {}", pos)),
+            None => fen.push_str(" -"),
+        }
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
+    }
+
+    // Identifies the position for the repetition rules: the same figures on
+    // the same squares, the same player to move and the same possible moves
+    pub fn position_key(&self) -> PositionKey {
+        // an en passant square only matters if a Pawn can actually take
+        let en_passant = self.en_passant.filter(|_| {
+            self.legal_moves().iter().any(|mv| matches!(mv, EnPassant(_,_,_)))
+        });
+        PositionKey {pieces: self.board.pieces,
+                     colors: self.board.colors,
+                     turn: self.turn,
+                     castling_rights: self.castling_rights(),
+                     en_passant}
+    }
+
+    // A hash of the position for the engine's transposition table, cheaper
+    // than position_key as the en passant square is taken as it is
+    #[cfg(feature = "engine")]
+    pub(crate) fn search_hash(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (self.board.pieces, self.board.colors, self.turn, self.castling_rights(), self.en_passant).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // How often the current position has occurred in the game, counting the
+    // current occurrence
+    pub fn repetitions(&self) -> usize {
+        let key = self.position_key();
+        let mut state = self.clone();
+        let mut count = 1;
+        // captures and Pawn moves cannot be undone, so earlier positions
+        // cannot be the same
+        for _ in 0..self.halfmove_clock {
+            if state.unmake_move().is_none() {
+                break;
+            }
+            if state.position_key() == key {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn player_is_in_check(&self) -> bool {
+        self.check_for_mate(self.turn)
+    }
+
+    // Whether the player could ever mate, assuming the worst play by the
+    // opponent. Only a lone King or a King with a single Bishop or Knight
+    // against a lone King count as insufficient.
+    pub fn has_mating_material(&self, color: Color) -> bool {
+        let board = &self.board;
+        let heavy = board.pieces(PAWN, color) | board.pieces(ROOK, color) | board.pieces(QUEEN, color);
+        if heavy != 0 {
+            return true;
+        }
+        let minors = (board.pieces(BISHOP, color) | board.pieces(KNIGHT, color)).count_ones();
+        let enemy_figures = board.color_pieces(color.other()).count_ones();
+        minors >= 2 || (minors == 1 && enemy_figures > 1)
+    }
+
+    pub fn player_has_moves(&self) -> bool {
+        !self.legal_moves().is_empty()
+    }
+
+    // All legal moves of the player whose turn it is
+    pub fn legal_moves(&self) -> MoveList {
+        let mut moves = MoveList::new();
+        self.generate_moves(&mut moves);
+        moves
+    }
+
+    pub fn legal_moves_filtered(&self, filter: MoveFilter) -> MoveList {
+        let mut moves = self.legal_moves();
+        match filter {
+            MoveFilter::All => (),
+            MoveFilter::Captures => moves.retain(|mv| mv.is_capture()),
+            MoveFilter::Checks => moves.retain(|mv| self.gives_check(*mv)),
+            MoveFilter::From(pos) => moves.retain(|mv| mv.from_pos() == pos),
+        }
+        moves
+    }
+
+    // Whether the move puts the opponent in check
+    pub fn gives_check(&self, mv: Move) -> bool {
+        let mut board = self.board.clone();
+        board.do_move(mv);
+        let enemy = self.turn.other();
+        match board.king_square(enemy) {
+            Some(king) => board.attackers(king, self.turn, board.occupied()) != 0,
+            None => false,
+        }
+    }
+
+    fn check_for_mate(&self, color: Color) -> bool {
+        match self.board.king_square(color) {
+            Some(king) => self.is_square_attacked(bitboard::pos(king), color.other()),
+            None => false,
+        }
+    }
+
+    // Whether a figure of the given color could take on the square, no
+    // matter whether its own King would be in check afterwards
+    pub fn is_square_attacked(&self, pos: Pos, by_color: Color) -> bool {
+        let square = bitboard::square(pos);
+        self.board.attackers(square, by_color, self.board.occupied()) != 0
+    }
+
+    // The positions of all figures of both colors attacking the square
+    pub fn attackers_of(&self, pos: Pos) -> Vec<Pos> {
+        let square = bitboard::square(pos);
+        let occupied = self.board.occupied();
+        let attackers = self.board.attackers(square, White, occupied)
+            | self.board.attackers(square, Black, occupied);
+        Squares(attackers).map(bitboard::pos).collect()
+    }
+
+    // The positions of the figures of the given color that may not leave the
+    // line between their King and an enemy Rook, Bishop or Queen
+    pub fn pinned_pieces(&self, color: Color) -> Vec<Pos> {
+        Squares(self.board.pinned(color)).map(bitboard::pos).collect()
+    }
+
+    // Pushes a move of the figure on from to to, taking whatever stands there
+    fn push_move(&self, moves: &mut MoveList, from: usize, to: usize) {
+        let (from_pos, to_pos) = (bitboard::pos(from), bitboard::pos(to));
+        let has_moved = self.board.fields_get(from_pos).unwrap().has_moved();
+        match self.board.fields_get(to_pos) {
+            Some(taken) => moves.push(Take(from_pos, to_pos, taken, has_moved)),
+            None => moves.push(Normal(from_pos, to_pos, has_moved)),
+        }
+    }
+
+    // Generates the legal moves directly instead of trying out every
+    // candidate move: pinned figures may only move along the line to their
+    // King and when in check only moves onto the check mask are allowed.
+    fn generate_moves(&self, moves: &mut MoveList) {
+        let board = &self.board;
+        let us = self.turn;
+        let them = us.other();
+        let own = board.color_pieces(us);
+        let enemy = board.color_pieces(them);
+        let occupied = own | enemy;
+        let king = match board.king_square(us) {
+            Some(king) => king,
+            None => return,
         };
-        match figure {
-            Pawn(_,_) => {
-                    let forward_pos = pos.advance(color).unwrap();
-                    if self.board.fields_get(forward_pos).is_none() {
-                        moves.push(Normal(pos,
-                                          forward_pos,
-                                          has_moved));
-                        if !has_moved {
-                            let double_forward_pos = forward_pos.advance(color)
-                                .unwrap();
-                            if self.board.fields_get(double_forward_pos).is_none() {
-                                moves.push(Normal(pos,
-                                                  double_forward_pos,
-                                                  has_moved));
-                            }
-                        }
-                    }
-                    for dir in [Left, Right] {
-                        if let Ok(attack_pos) = forward_pos.move_dir(dir) {
-                            let new_field = self.board.fields_get(attack_pos);
-                            if let Some(attacked_fig) = new_field {
-                                if attacked_fig.color() != color {
-                                    moves.push(Take(pos,
-                                                    attack_pos,
-                                                    attacked_fig,
-                                                    has_moved));
-                                }
-                            }
-                        }
+
+        // the King may not stay on the line of a slider, so remove it from
+        // the occupancy when looking for attacked squares
+        let without_king = occupied & !bitboard::bit(king);
+        for to in Squares(bitboard::king_attacks(king) & !own) {
+            if board.attackers(to, them, without_king) == 0 {
+                self.push_move(moves, king, to);
+            }
+        }
+
+        let checkers = board.attackers(king, them, occupied);
+        if checkers.count_ones() > 1 {
+            // only the King can get out of a double check
+            return;
+        }
+        let check_mask = match Squares(checkers).next() {
+            Some(checker) => bitboard::between(king, checker) | checkers,
+            None => !0,
+        };
+        let pinned = board.pinned(us);
+        let allowed = |from: usize| {
+            if pinned & bitboard::bit(from) != 0 {
+                check_mask & bitboard::line(king, from)
+            } else {
+                check_mask
+            }
+        };
+
+        for from in Squares(board.pieces(KNIGHT, us) & !pinned) {
+            for to in Squares(bitboard::knight_attacks(from) & !own & check_mask) {
+                self.push_move(moves, from, to);
+            }
+        }
+        for from in Squares(board.pieces(BISHOP, us) | board.pieces(QUEEN, us)) {
+            for to in Squares(bitboard::bishop_attacks(from, occupied) & !own & allowed(from)) {
+                self.push_move(moves, from, to);
+            }
+        }
+        for from in Squares(board.pieces(ROOK, us) | board.pieces(QUEEN, us)) {
+            for to in Squares(bitboard::rook_attacks(from, occupied) & !own & allowed(from)) {
+                self.push_move(moves, from, to);
+            }
+        }
+
+        self.generate_pawn_moves(moves, king, &allowed);
+
+        if checkers == 0 {
+            self.generate_castle_moves(moves, king);
+        }
+    }
+
+    fn generate_pawn_moves(&self,
+                           moves: &mut MoveList,
+                           king: usize,
+                           allowed: &dyn Fn(usize) -> Bitboard) {
+        let board = &self.board;
+        let us = self.turn;
+        let them = us.other();
+        let occupied = board.occupied();
+        let (forward, last_rank): (isize, usize) = match us {
+            White => (8, 8),
+            Black => (-8, 1),
+        };
+        for from in Squares(board.pieces(PAWN, us)) {
+            let from_pos = bitboard::pos(from);
+            let has_moved = board.fields_get(from_pos).unwrap().has_moved();
+            let allowed = allowed(from);
+            let mut targets = bitboard::pawn_attacks(us, from) & board.color_pieces(them);
+            if let Some(one) = from.checked_add_signed(forward).filter(|sq| *sq < 64) {
+                if occupied & bitboard::bit(one) == 0 {
+                    targets |= bitboard::bit(one);
+                    let two = one.wrapping_add_signed(forward);
+                    if !has_moved && two < 64 && occupied & bitboard::bit(two) == 0 {
+                        targets |= bitboard::bit(two);
                     }
-                },
-            Rook(_,_) => push_moves_line(vec![Left, Right, Up, Down], false),
-            Knight(_,_) => {
-                let dirs = [Left, Right, Up, Down];
-                for dir in dirs {
-                    if let Ok(one_moved_pos) = pos.move_dir(dir) {
-                        for diag in dir.get_diagonals() {
-                            if let Ok(jump_pos) = one_moved_pos.move_dir(diag) {
-                                match self.board.fields_get(jump_pos) {
-                                    Some(attacked_fig) => {
-                                        if attacked_fig.color() != color {
-                                            moves.push(Take(pos,
-                                                            jump_pos,
-                                                            attacked_fig,
-                                                            has_moved));
-                                        }
-                                    },
-                                    None => moves.push(Normal(pos, jump_pos, has_moved)),
-                                }
-                            }
-                        }
+                }
+            }
+            for to in Squares(targets & allowed) {
+                let to_pos = bitboard::pos(to);
+                if to_pos.rank == last_rank {
+                    let taken = board.fields_get(to_pos);
+                    for fig in [Queen(us, true), Rook(us, true), Bishop(us, true), Knight(us, true)] {
+                        moves.push(Promote(from_pos, to_pos, fig, taken));
                     }
+                } else {
+                    self.push_move(moves, from, to);
                 }
-            },
-            Bishop(_,_) => push_moves_line(vec![LeftUp, LeftDown, RightUp, RightDown], false),
-            Queen(_,_) => push_moves_line(vec![Left, Right, Up, Down,
-                                          LeftUp, LeftDown, RightUp, RightDown], false),
-            King(_,_) => push_moves_line(vec![Left, Right, Up, Down,
-                                          LeftUp, LeftDown, RightUp, RightDown], true),
+            }
+
+            let ep = match self.en_passant {
+                Some(ep) => bitboard::square(ep),
+                None => continue,
+            };
+            if bitboard::pawn_attacks(us, from) & bitboard::bit(ep) == 0 {
+                continue;
+            }
+            // taking en passant removes two figures from a rank at once, so
+            // just look whether the King is attacked afterwards
+            let taken = bitboard::square(Pos::new(bitboard::pos(ep).file, from_pos.rank).unwrap());
+            let after = (occupied & !bitboard::bit(from) & !bitboard::bit(taken)) | bitboard::bit(ep);
+            if board.attackers(king, them, after) & !bitboard::bit(taken) == 0 {
+                moves.push(EnPassant(from_pos, bitboard::pos(ep), bitboard::pos(taken)));
+            }
+        }
+    }
+
+    fn generate_castle_moves(&self, moves: &mut MoveList, king: usize) {
+        let color = self.turn;
+        let king_pos = bitboard::pos(king);
+        let rank = match color {
+            White => 1,
+            Black => 8,
         };
-        if check_for_mate {
-            moves.retain(|mv| self.check_move_for_mate(*mv));
+        if king_pos != Pos::new(5, rank).unwrap() || self.board.fields_get(king_pos).unwrap().has_moved() {
+            return;
         }
-        moves
+        let occupied = self.board.occupied();
+        // (Rook file, King file after castling, Rook file after castling)
+        for (rook_file, king_file, rook_new_file) in [(8, 7, 6), (1, 3, 4)] {
+            let rook_pos = Pos::new(rook_file, rank).unwrap();
+            match self.board.fields_get(rook_pos) {
+                Some(Rook(c, false)) if c == color => (),
+                _ => continue,
+            }
+            if bitboard::between(king, bitboard::square(rook_pos)) & occupied != 0 {
+                continue;
+            }
+            // the King may not pass through or land on an attacked square
+            let king_new_pos = Pos::new(king_file, rank).unwrap();
+            let passed = bitboard::between(king, bitboard::square(king_new_pos))
+                | bitboard::bit(bitboard::square(king_new_pos));
+            if Squares(passed).any(|sq| self.board.attackers(sq, color.other(), occupied) != 0) {
+                continue;
+            }
+            moves.push(Castle(king_pos,
+                              rook_pos,
+                              king_new_pos,
+                              Pos::new(rook_new_file, rank).unwrap()));
+        }
+    }
+
+    // Plays a move without checking it, it has to come from legal_moves
+    pub fn make_move(&mut self, mv: Move) {
+        self.history.push(HistoryEntry{mv,
+                                       en_passant: self.en_passant,
+                                       halfmove_clock: self.halfmove_clock});
+        let is_pawn_move = matches!(self.board.fields_get(mv.from_pos()), Some(Pawn(_, _)));
+        self.board.do_move(mv);
+        self.en_passant = match mv {
+            Normal(old_pos, new_pos, _) if is_pawn_move && old_pos.rank.abs_diff(new_pos.rank) == 2 =>
+                Some(Pos::new(old_pos.file, (old_pos.rank + new_pos.rank) / 2).unwrap()),
+            _ => None,
+        };
+        if is_pawn_move || mv.taken().is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if self.turn == Black {
+            self.fullmove_number += 1;
+        }
+        self.turn = self.turn.other();
+    }
+
+    // The moves played so far, the first move first
+    pub fn history(&self) -> impl DoubleEndedIterator<Item = Move> + ExactSizeIterator + '_ {
+        self.history.iter().map(|entry| entry.mv)
     }
 
-    pub fn move_checked(&mut self, pos: Pos, new_pos: Pos) -> Result<(), ()> {
-        match self.board.fields_get(pos) {
-            None => return Err(()),
-            Some(fig) => if fig.color() != self.turn {return Err(());},
+    // Takes back the last move made, returning it
+    pub fn unmake_move(&mut self) -> Option<Move> {
+        let entry = self.history.pop()?;
+        self.turn = self.turn.other();
+        if self.turn == Black {
+            self.fullmove_number -= 1;
         }
-        let possible_moves = self.possible_moves(pos, true);
-        let mv_ind = possible_moves
+        self.board.undo_move(entry.mv);
+        self.en_passant = entry.en_passant;
+        self.halfmove_clock = entry.halfmove_clock;
+        Some(entry.mv)
+    }
+
+    // Plays the move of the figure on pos to new_pos if it is legal. Pawns
+    // reaching the last rank become Queens.
+    pub fn move_checked(&mut self, pos: Pos, new_pos: Pos) -> Result<(), MoveError> {
+        self.promote_checked(pos, new_pos, Queen(self.turn, true))
+    }
+
+    // Like move_checked, with the figure a Pawn reaching the last rank
+    // becomes. Only its kind matters.
+    pub fn promote_checked(&mut self, pos: Pos, new_pos: Pos, promotion: Figure) -> Result<(), MoveError> {
+        let figure = match self.board.fields_get(pos) {
+            None => return Err(MoveError::NoFigure),
+            Some(fig) if fig.color() != self.turn => return Err(MoveError::OpponentsFigure),
+            Some(fig) => fig,
+        };
+        let possible_moves = self.legal_moves_filtered(MoveFilter::From(pos));
+        let mut candidates = possible_moves
             .iter()
-            .position(|mv| {
-                pos == mv.from_pos() && new_pos == mv.to_pos()
+            .filter(|mv| pos == mv.from_pos() && new_pos == mv.to_pos())
+            .peekable();
+        if candidates.peek().is_none() {
+            return Err(self.illegal_move_reason(pos, new_pos, figure));
+        }
+        let mv = *candidates
+            .find(|mv| match mv {
+                Promote(_, _, fig, _) => fig.kind_index() == promotion.kind_index(),
+                _ => true,
             })
-            .ok_or(())?;
-        self.board.do_move(possible_moves[mv_ind]);
-        self.turn = self.turn.other();
+            .ok_or(MoveError::InvalidPromotion)?;
+        self.make_move(mv);
         Ok(())
     }
+
+    // Tells why the figure cannot move from pos to new_pos, when no legal
+    // move does so
+    fn illegal_move_reason(&self, pos: Pos, new_pos: Pos, figure: Figure) -> MoveError {
+        let board = &self.board;
+        let us = self.turn;
+        let (from, to) = (bitboard::square(pos), bitboard::square(new_pos));
+        let occupied = board.occupied();
+        let home_rank = match us {
+            White => 1,
+            Black => 8,
+        };
+        if let King(_, has_moved) = figure {
+            if pos == Pos::new(5, home_rank).unwrap() && new_pos.rank == home_rank && new_pos.file.abs_diff(5) == 2 {
+                let rook_pos = Pos::new(if new_pos.file == 7 { 8 } else { 1 }, home_rank).unwrap();
+                let rook_moved = !matches!(board.fields_get(rook_pos), Some(Rook(c, false)) if c == us);
+                let passed = bitboard::square(Pos::new((5 + new_pos.file) / 2, home_rank).unwrap());
+                return if has_moved || rook_moved {
+                    MoveError::CastlingRightsLost
+                } else if bitboard::between(from, bitboard::square(rook_pos)) & occupied != 0 {
+                    MoveError::CastlingBlocked
+                } else if self.player_is_in_check() {
+                    MoveError::CastlingOutOfCheck
+                } else if board.attackers(passed, us.other(), occupied) != 0 {
+                    MoveError::CastlingThroughCheck
+                } else {
+                    MoveError::CastlingIntoCheck
+                };
+            }
+        }
+        if board.color_pieces(us) & bitboard::bit(to) != 0 {
+            return MoveError::OccupiedByOwnFigure;
+        }
+        // where the figure could go if its King were safe
+        let reachable = match figure {
+            Pawn(_, has_moved) => {
+                let enemy = board.color_pieces(us.other()) | self.en_passant.map_or(0, |ep| bitboard::bit(bitboard::square(ep)));
+                let mut reachable = bitboard::pawn_attacks(us, from) & enemy;
+                let forward: isize = if us == White { 8 } else { -8 };
+                let one = from.wrapping_add_signed(forward);
+                if one < 64 && occupied & bitboard::bit(one) == 0 {
+                    reachable |= bitboard::bit(one);
+                    let two = one.wrapping_add_signed(forward);
+                    if !has_moved && two < 64 && occupied & bitboard::bit(two) == 0 {
+                        reachable |= bitboard::bit(two);
+                    }
+                }
+                reachable
+            },
+            Rook(_,_) => bitboard::rook_attacks(from, occupied),
+            Knight(_,_) => bitboard::knight_attacks(from),
+            Bishop(_,_) => bitboard::bishop_attacks(from, occupied),
+            Queen(_,_) => bitboard::queen_attacks(from, occupied),
+            King(_,_) => bitboard::king_attacks(from),
+        };
+        if reachable & bitboard::bit(to) == 0 {
+            MoveError::Unreachable
+        } else if self.player_is_in_check() {
+            MoveError::InCheck
+        } else {
+            MoveError::LeavesKingInCheck
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(s: &str) -> Pos {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn square_attacked() {
+        let state = ChessState::new();
+        assert!(state.is_square_attacked(pos("f3"), White));
+        assert!(state.is_square_attacked(pos("d2"), White));
+        assert!(!state.is_square_attacked(pos("e4"), White));
+        assert!(state.is_square_attacked(pos("e6"), Black));
+        assert!(!state.is_square_attacked(pos("e3"), Black));
+    }
+
+    #[test]
+    fn attackers() {
+        let state = ChessState::from_fen("4k3/8/8/3p4/4P3/2N5/8/4K3 w - - 0 1").unwrap();
+        let mut attackers = state.attackers_of(pos("d5"));
+        attackers.sort_by_key(|p| (p.file, p.rank));
+        assert!(attackers == vec![pos("c3"), pos("e4")]);
+        let mut attackers = state.attackers_of(pos("e4"));
+        attackers.sort_by_key(|p| (p.file, p.rank));
+        assert!(attackers == vec![pos("c3"), pos("d5")]);
+        assert!(state.attackers_of(pos("a8")).is_empty());
+    }
+
+    #[test]
+    fn pinned() {
+        let state = ChessState::from_fen("4k3/4r3/8/b7/8/2N5/4B3/4K3 w - - 0 1").unwrap();
+        let mut pinned = state.pinned_pieces(White);
+        pinned.sort_by_key(|p| (p.file, p.rank));
+        assert!(pinned == vec![pos("c3"), pos("e2")]);
+        assert!(state.pinned_pieces(Black).is_empty());
+    }
+
+    #[test]
+    fn filtered_moves() {
+        let state = ChessState::from_fen("4k3/8/8/3p4/4P3/8/8/R3K3 w - - 0 1").unwrap();
+        let captures = state.legal_moves_filtered(MoveFilter::Captures);
+        assert!(captures.len() == 1 && captures[0].to_pos() == pos("d5"));
+        let checks = state.legal_moves_filtered(MoveFilter::Checks);
+        assert!(checks.len() == 1 && checks[0].to_pos() == pos("a8"));
+        assert_eq!(state.legal_moves_filtered(MoveFilter::From(pos("e4"))).len(), 2);
+    }
+
+    // The JSON schema is relied upon by other services, these must not change
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_schema() {
+        assert_eq!(serde_json::to_string(&pos("e2")).unwrap(), r#"{"file":5,"rank":2}"#);
+        assert_eq!(serde_json::to_string(&Pawn(White, false)).unwrap(), r#"{"Pawn":["White",false]}"#);
+        let mv = Take(pos("e4"), pos("d5"), Pawn(Black, true), true);
+        assert_eq!(serde_json::to_string(&mv).unwrap(),
+                   r#"{"Take":[{"file":5,"rank":4},{"file":4,"rank":5},{"Pawn":["Black",true]},true]}"#);
+    }
+
+    // Positions from other services are checked
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_validation() {
+        assert_eq!(serde_json::from_str::<Pos>(r#"{"file":5,"rank":8}"#).unwrap(), pos("e8"));
+        assert!(serde_json::from_str::<Pos>(r#"{"file":0,"rank":9}"#).is_err());
+        assert!(serde_json::from_str::<Move>(r#"{"Normal":[{"file":5,"rank":2},{"file":5,"rank":9},true]}"#).is_err());
+        let json = serde_json::to_string(&Board::new()).unwrap();
+        assert_eq!(serde_json::from_str::<Board>(&json).unwrap(), Board::new());
+        let mut board = Board::new();
+        board.remove_figure(pos("e8"));
+        let json = serde_json::to_string(&board).unwrap();
+        assert!(serde_json::from_str::<Board>(&json).is_err());
+        let mut board = Board::new();
+        board.add_figure(pos("a4"), Queen(White, true));
+        let json = serde_json::to_string(&board).unwrap();
+        assert!(serde_json::from_str::<Board>(&json).unwrap_err().to_string().contains("more than 16"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_state_validation() {
+        let mut state = ChessState::from_fen("6k1/5ppp/8/8/8/8/4P3/R5K1 w - - 0 1").unwrap();
+        for (from, to) in [("e2", "e4"), ("g8", "h8"), ("a1", "a8")] {
+            state.move_checked(pos(from), pos(to)).unwrap();
+        }
+        let json = serde_json::to_string(&state).unwrap();
+        let restored: ChessState = serde_json::from_str(&json).unwrap();
+        assert!(restored.mate);
+        assert_eq!(restored.history().len(), 3);
+        // a move that was not played
+        let tampered = json.replacen(r#"{"file":5,"rank":4}"#, r#"{"file":5,"rank":3}"#, 1);
+        assert!(serde_json::from_str::<ChessState>(&tampered).is_err());
+        // an en passant square without the Pawn that skipped it
+        let state = ChessState::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        let json = serde_json::to_string(&state).unwrap().replace(r#""en_passant":null"#, r#""en_passant":{"file":4,"rank":6}"#);
+        assert!(serde_json::from_str::<ChessState>(&json).unwrap_err().to_string().contains("en passant"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut state = ChessState::new();
+        for (from, to) in [("e2", "e4"), ("d7", "d5"), ("e4", "d5")] {
+            state.move_checked(pos(from), pos(to)).unwrap();
+        }
+        let json = serde_json::to_string(&state).unwrap();
+        let mut restored: ChessState = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, state);
+        assert_eq!(restored.legal_moves().len(), state.legal_moves().len());
+        restored.unmake_move();
+        state.unmake_move();
+        assert_eq!(restored, state);
+    }
+
+    #[test]
+    fn move_errors() {
+        let check = |fen: &str, from: &str, to: &str, error: MoveError| {
+            let mut state = ChessState::from_fen(fen).unwrap();
+            assert_eq!(state.move_checked(pos(from), pos(to)), Err(error), "{} {}{}", fen, from, to);
+        };
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        check(start, "e4", "e5", MoveError::NoFigure);
+        check(start, "e7", "e5", MoveError::OpponentsFigure);
+        check(start, "e2", "e5", MoveError::Unreachable);
+        check(start, "f1", "c4", MoveError::Unreachable);
+        check(start, "d1", "d2", MoveError::OccupiedByOwnFigure);
+        check(start, "e1", "g1", MoveError::CastlingBlocked);
+        // the Knight on c3 is pinned
+        check("4k3/8/8/b7/8/2N5/8/4K3 w - - 0 1", "c3", "d5", MoveError::LeavesKingInCheck);
+        check("4k3/8/8/8/8/8/3r4/4K3 w - - 0 1", "e1", "e2", MoveError::LeavesKingInCheck);
+        check("4k3/8/8/8/8/8/r7/R3K2R w KQ - 0 1", "a1", "a3", MoveError::Unreachable);
+        check("4k3/8/8/8/8/8/4q3/R3K2R w KQ - 0 1", "e1", "g1", MoveError::CastlingOutOfCheck);
+        check("4k3/8/8/8/8/8/4r3/R3K1NR w KQ - 0 1", "h1", "h2", MoveError::InCheck);
+        check("4k3/8/8/8/8/8/5r2/R3K2R w KQ - 0 1", "e1", "g1", MoveError::CastlingThroughCheck);
+        check("4k3/8/8/8/8/8/6r1/R3K2R w KQ - 0 1", "e1", "g1", MoveError::CastlingIntoCheck);
+        check("4k3/8/8/8/8/8/8/R3K2R w Q - 0 1", "e1", "g1", MoveError::CastlingRightsLost);
+
+        let mut state = ChessState::from_fen("8/4P1k1/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(state.promote_checked(pos("e7"), pos("e8"), King(White, true)), Err(MoveError::InvalidPromotion));
+        state.promote_checked(pos("e7"), pos("e8"), Knight(White, true)).unwrap();
+        assert_eq!(state.board.fields_get(pos("e8")), Some(Knight(White, true)));
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                    "8/8/8/3pP3/8/8/8/4K2k w - d6 0 3"] {
+            assert_eq!(ChessState::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn impossible_fens() {
+        // too many figures for a MoveList to hold the moves
+        assert!(ChessState::from_fen("QQQQQQQQ/QQQQQQQQ/QQQQQQQQ/8/8/8/8/k6K w - - 0 1").is_err());
+        for fen in ["8/8/8/8/8/8/8/7K w - - 0 1",
+                    "4k3/8/8/8/8/8/8/3KK3 w - - 0 1",
+                    "4k3/8/8/8/8/PPPPPPPP/P7/4K3 w - - 0 1",
+                    "4k2P/8/8/8/8/8/8/4K3 w - - 0 1",
+                    // no Pawn skipped the en passant square
+                    "4k3/8/8/8/4P3/8/8/4K3 w - e3 0 1",
+                    "4k3/8/8/8/4P3/8/8/4K3 b - d3 0 1"] {
+            assert!(ChessState::from_fen(fen).is_err(), "{}", fen);
+        }
+        // nine Queens after promotions are possible
+        assert!(ChessState::from_fen("4k3/8/8/8/8/8/QQQQQQQQ/3QK3 w - - 0 1").is_ok());
+        assert!(ChessState::from_fen("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").is_ok());
+    }
+
+    #[test]
+    fn repetitions() {
+        let mut state = ChessState::new();
+        assert_eq!(state.repetitions(), 1);
+        for _ in 0..2 {
+            for (from, to) in [("g1", "f3"), ("g8", "f6"), ("f3", "g1"), ("f6", "g8")] {
+                state.move_checked(pos(from), pos(to)).unwrap();
+            }
+        }
+        assert_eq!(state.repetitions(), 3);
+        state.move_checked(pos("e2"), pos("e4")).unwrap();
+        assert_eq!(state.repetitions(), 1);
+    }
 }
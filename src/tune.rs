@@ -0,0 +1,202 @@
+use crate::eval::{self, Score, Weights};
+use crate::state::{ChessState, Color};
+
+// A position of the dataset with how the game ended
+pub struct Sample {
+    // the features of the position as indices into Weights::values, counted
+    // for White minus for Black
+    features: Vec<(usize, i32)>,
+    phase: i32,
+    // 1 if White won, 0.5 for a draw and 0 if Black won
+    result: f64,
+}
+
+impl Sample {
+    pub fn new(state: &ChessState, result: f64) -> Self {
+        let mut features: Vec<(usize, i32)> = vec![];
+        eval::features(state, |_, color, i, count| {
+            let count = match color {
+                Color::White => count,
+                Color::Black => -count,
+            };
+            match features.iter_mut().find(|(j, _)| *j == i) {
+                Some((_, total)) => *total += count,
+                None => features.push((i, count)),
+            }
+        });
+        features.retain(|(_, count)| *count != 0);
+        Sample {features, phase: eval::phase(state), result}
+    }
+
+    // The evaluation from White's point of view
+    fn evaluate(&self, values: &[[f64; 2]]) -> f64 {
+        let (mg, eg) = self.features.iter().fold((0.0, 0.0), |(mg, eg), &(i, count)| {
+            (mg + values[i][0] * count as f64, eg + values[i][1] * count as f64)
+        });
+        let phase = self.phase as f64 / eval::MAX_PHASE as f64;
+        mg * phase + eg * (1.0 - phase)
+    }
+}
+
+// Reads a result like 1-0, "0-1", 1/2-1/2 or 0.5
+fn parse_result(s: &str) -> Result<f64, ()> {
+    match s.trim().trim_end_matches(';').trim_matches('"') {
+        "1-0" => Ok(1.0),
+        "0-1" => Ok(0.0),
+        "1/2-1/2" => Ok(0.5),
+        s => s.parse().ok().filter(|result| (0.0..=1.0).contains(result)).ok_or(()),
+    }
+}
+
+// Reads a line of the dataset: a FEN followed by the result, in brackets,
+// as a c9 opcode like in EPD files or as the last word, e.g.
+//   rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1 [0.5]
+//   rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - c9 "1/2-1/2";
+pub fn parse_line(line: &str) -> Result<(ChessState, f64), ()> {
+    let (fen, result) = if let Some((fen, rest)) = line.split_once('[') {
+        (fen, rest.trim_end().strip_suffix(']').ok_or(())?)
+    } else if let Some((fen, rest)) = line.split_once(" c9 ") {
+        (fen, rest)
+    } else {
+        line.trim_end().rsplit_once(char::is_whitespace).ok_or(())?
+    };
+    let mut fen = fen.trim().to_owned();
+    // EPD positions come without the move counters
+    if fen.split_whitespace().count() == 4 {
+        fen.push_str(" 0 1");
+    }
+    Ok((ChessState::from_fen(&fen)?, parse_result(result)?))
+}
+
+// Reads the positions of a dataset, one per line. Empty lines and lines
+// starting with '#' are skipped.
+pub fn parse(text: &str) -> Result<Vec<Sample>, String> {
+    let mut samples = vec![];
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (state, result) = parse_line(line).map_err(|_| format!("line {}: invalid position or result", number + 1))?;
+        samples.push(Sample::new(&state, result));
+    }
+    Ok(samples)
+}
+
+// The chance for White to win predicted from an evaluation in centipawns
+fn sigmoid(k: f64, evaluation: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * evaluation / 400.0))
+}
+
+// The mean squared difference between the results and the predictions
+fn error(samples: &[Sample], values: &[[f64; 2]], k: f64) -> f64 {
+    let sum: f64 = samples.iter().map(|sample| (sample.result - sigmoid(k, sample.evaluate(values))).powi(2)).sum();
+    sum / samples.len().max(1) as f64
+}
+
+fn to_values(weights: &Weights) -> Vec<[f64; 2]> {
+    weights.values().iter().map(|score| [score.mg as f64, score.eg as f64]).collect()
+}
+
+// The scaling of the sigmoid that fits the results best to the weights,
+// by ternary search
+pub fn fit_k(samples: &[Sample], weights: &Weights) -> f64 {
+    let values = to_values(weights);
+    let (mut low, mut high) = (0.01, 4.0);
+    for _ in 0..60 {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        if error(samples, &values, a) < error(samples, &values, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    (low + high) / 2.0
+}
+
+// Optimises the weights by minimising the error of the sigmoid of the
+// evaluation against the results (the Texel method), with the Adam variant
+// of gradient descent. The evaluation is linear in the weights, so the
+// gradient comes straight from the features. Progress is reported with the
+// iteration and the error before it.
+pub fn tune(samples: &[Sample],
+            weights: &Weights,
+            k: f64,
+            iterations: u32,
+            rate: f64,
+            mut progress: impl FnMut(u32, f64)) -> Weights {
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+    const EPSILON: f64 = 1e-8;
+    let mut values = to_values(weights);
+    let mut momentum = vec![[0.0; 2]; values.len()];
+    let mut velocity = vec![[0.0; 2]; values.len()];
+    for iteration in 1..=iterations {
+        progress(iteration, error(samples, &values, k));
+        let mut gradient = vec![[0.0; 2]; values.len()];
+        for sample in samples {
+            let predicted = sigmoid(k, sample.evaluate(&values));
+            // the derivative of the squared error by the evaluation
+            let slope = -2.0 * (sample.result - predicted) * predicted * (1.0 - predicted) * k * 10f64.ln() / 400.0;
+            let phase = sample.phase as f64 / eval::MAX_PHASE as f64;
+            for &(i, count) in &sample.features {
+                gradient[i][0] += slope * count as f64 * phase;
+                gradient[i][1] += slope * count as f64 * (1.0 - phase);
+            }
+        }
+        for i in 0..values.len() {
+            for j in 0..2 {
+                let g = gradient[i][j] / samples.len() as f64;
+                momentum[i][j] = BETA1 * momentum[i][j] + (1.0 - BETA1) * g;
+                velocity[i][j] = BETA2 * velocity[i][j] + (1.0 - BETA2) * g * g;
+                let m = momentum[i][j] / (1.0 - BETA1.powi(iteration as i32));
+                let v = velocity[i][j] / (1.0 - BETA2.powi(iteration as i32));
+                values[i][j] -= rate * m / (v.sqrt() + EPSILON);
+            }
+        }
+    }
+    progress(iterations + 1, error(samples, &values, k));
+    let mut tuned = weights.clone();
+    for (score, [mg, eg]) in tuned.values_mut().iter_mut().zip(values) {
+        *score = Score::new(mg.round() as i32, eg.round() as i32);
+    }
+    tuned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{KNIGHT, QUEEN};
+
+    #[test]
+    fn parse_lines() {
+        let (state, result) = parse_line("4k3/8/8/8/8/8/8/4KQ2 w - - 0 1 [1.0]").unwrap();
+        assert_eq!(state.to_fen(), "4k3/8/8/8/8/8/8/4KQ2 w - - 0 1");
+        assert_eq!(result, 1.0);
+        assert_eq!(parse_line("4k3/8/8/8/8/8/8/4KQ2 w - - c9 \"1/2-1/2\";").unwrap().1, 0.5);
+        assert_eq!(parse_line("4k3/8/8/8/8/8/8/4KQ2 w - - 0 1 0-1").unwrap().1, 0.0);
+        assert!(parse_line("4k3/8/8/8/8/8/8/4KQ2 w - - 0 1 [2]").is_err());
+        let error = parse("# comment\n\n4k3/8/8/8/8/8/8/4KQ2 w - - 0 1 won\n").err().unwrap();
+        assert!(error.starts_with("line 3:"), "{}", error);
+    }
+
+    #[test]
+    fn learns_from_results() {
+        // a Knight up wins, equal material draws
+        let text = "4k3/8/8/8/8/8/8/3NK3 w - - 0 1 1-0\n\
+                    3nk3/8/8/8/8/8/8/4K3 w - - 0 1 0-1\n\
+                    4k3/8/8/8/8/8/8/4K3 w - - 0 1 1/2-1/2\n\
+                    3nk3/8/8/8/8/8/8/3NK3 w - - 0 1 1/2-1/2\n";
+        let samples = parse(text).unwrap();
+        let mut weights = Weights::default();
+        weights.values_mut()[eval::MATERIAL + KNIGHT] = Score::new(20, 20);
+        let k = fit_k(&samples, &weights);
+        assert!(k > 1.0, "{}", k);
+        let mut errors = vec![];
+        let tuned = tune(&samples, &weights, 1.0, 200, 2.0, |_, error| errors.push(error));
+        assert_eq!(errors.len(), 201);
+        assert!(errors.last().unwrap() < &(errors[0] / 2.0), "{:?}", errors);
+        assert!(tuned.values()[eval::MATERIAL + KNIGHT].eg > 100);
+        assert_eq!(tuned.values()[eval::MATERIAL + QUEEN], weights.values()[eval::MATERIAL + QUEEN]);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::state::{ChessState, Move};
+
+// One step of the tutorial: a position with a task for White
+pub struct Lesson {
+    pub title: &'static str,
+    pub fen: &'static str,
+    pub task: &'static str,
+    // the move the learner has to make, as the squares it goes from and to
+    // in UCI notation without the promotion, e.g. "e1g1" for castling
+    pub solution: &'static str,
+    // shown after a wrong or illegal move
+    pub hint: &'static str,
+}
+
+pub const LESSONS: [Lesson; 10] = [
+    Lesson {title: "The rook",
+            fen: "7k/8/8/8/8/8/8/R6K w - - 0 1",
+            task: "Move the rook to a7.",
+            solution: "a1a7",
+            hint: "Rooks move any number of squares up, down, left or right."},
+    Lesson {title: "The bishop",
+            fen: "7k/8/8/8/8/8/8/2B4K w - - 0 1",
+            task: "Move the bishop to h6.",
+            solution: "c1h6",
+            hint: "Bishops move diagonally and stay on the colour they start on."},
+    Lesson {title: "The queen",
+            fen: "7k/8/8/8/8/8/8/3Q3K w - - 0 1",
+            task: "Move the queen to h5.",
+            solution: "d1h5",
+            hint: "The queen moves like a rook or like a bishop."},
+    Lesson {title: "The knight",
+            fen: "7k/8/8/8/8/8/8/1N5K w - - 0 1",
+            task: "Move the knight to c3.",
+            solution: "b1c3",
+            hint: "Knights jump two squares in one direction and one to the side."},
+    Lesson {title: "The king",
+            fen: "7k/8/8/8/8/8/8/4K3 w - - 0 1",
+            task: "Move the king to d2.",
+            solution: "e1d2",
+            hint: "The king moves one square in any direction."},
+    Lesson {title: "The pawn",
+            fen: "7k/8/8/8/8/8/4P3/7K w - - 0 1",
+            task: "Move the pawn two squares forward to e4.",
+            solution: "e2e4",
+            hint: "Pawns move one square forward, or two from their starting square."},
+    Lesson {title: "Capturing with pawns",
+            fen: "7k/8/8/8/3p4/4P3/8/7K w - - 0 1",
+            task: "Take the black pawn on d4.",
+            solution: "e3d4",
+            hint: "Pawns capture one square diagonally forward."},
+    Lesson {title: "Promotion",
+            fen: "7k/4P3/8/8/8/8/8/7K w - - 0 1",
+            task: "Move the pawn to the last rank to promote it.",
+            solution: "e7e8",
+            hint: "A pawn reaching the last rank becomes a queen, rook, bishop or knight."},
+    Lesson {title: "Castling",
+            fen: "7k/8/8/8/8/8/8/4K2R w K - 0 1",
+            task: "Castle: move the king two squares towards the rook.",
+            solution: "e1g1",
+            hint: "When castling the king moves two squares and the rook jumps over it."},
+    Lesson {title: "Checkmate",
+            fen: "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            task: "Checkmate the black king with the rook.",
+            solution: "a1a8",
+            hint: "The black king is trapped on the last rank by its own pawns."},
+];
+
+// Where the learner is in the tutorial
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Tutorial {
+    step: usize,
+}
+
+impl Tutorial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lesson(&self) -> Option<&'static Lesson> {
+        LESSONS.get(self.step)
+    }
+
+    // The number of the current lesson, counting from one
+    pub fn number(&self) -> usize {
+        self.step + 1
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.step >= LESSONS.len()
+    }
+
+    // The position of the current lesson
+    pub fn position(&self) -> Option<ChessState> {
+        self.lesson().map(|lesson| ChessState::from_fen(lesson.fen).unwrap())
+    }
+
+    // Checks a legal move made in the position of the current lesson, moving
+    // on to the next lesson if it solves the task
+    pub fn check(&mut self, mv: Move) -> bool {
+        match self.lesson() {
+            Some(lesson) if solves(mv, lesson) => {
+                self.step += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+fn solves(mv: Move, lesson: &Lesson) -> bool {
+    format!("{}{}", mv.from_pos(), mv.to_pos()) == lesson.solution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lessons_can_be_solved() {
+        let mut tutorial = Tutorial::new();
+        while let Some(state) = tutorial.position() {
+            let lesson = tutorial.lesson().unwrap();
+            let moves = state.legal_moves();
+            let wrong = moves.iter().find(|mv| !solves(**mv, lesson));
+            assert!(!tutorial.check(*wrong.unwrap()));
+            // other figures reaching the same square do not solve it
+            for mv in moves.iter().filter(|mv| mv.to_pos().to_string() == lesson.solution[2..] && !solves(**mv, lesson)) {
+                assert!(!tutorial.check(*mv), "{} {}", lesson.title, mv);
+            }
+            let solution = moves.iter().find(|mv| solves(**mv, lesson));
+            assert!(tutorial.check(*solution.unwrap()), "{}", lesson.title);
+        }
+        assert!(tutorial.is_finished());
+        assert_eq!(tutorial.number(), LESSONS.len() + 1);
+    }
+
+    #[test]
+    fn target_square_is_not_enough() {
+        let mut tutorial = Tutorial {step: 8};
+        assert_eq!(tutorial.lesson().unwrap().title, "Castling");
+        let state = tutorial.position().unwrap();
+        // the rook may also go to g1
+        let rook_move = crate::notation::parse_uci(&state, "h1g1").unwrap();
+        assert!(!tutorial.check(rook_move));
+        assert!(tutorial.check(crate::notation::parse_uci(&state, "e1g1").unwrap()));
+    }
+}
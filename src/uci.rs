@@ -0,0 +1,242 @@
+use crate::book::Book;
+use crate::engine::{self, SearchResult};
+use crate::eval::Weights;
+use crate::notation;
+use crate::state::{ChessState, Color};
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ScopedJoinHandle};
+use std::time::{Duration, Instant};
+
+// Sets up the position of a "position" command, e.g.
+// "position startpos moves e2e4 e7e5" or "position fen <fen> moves ..."
+fn parse_position(args: &[&str]) -> Result<ChessState, ()> {
+    let moves_at = args.iter().position(|&arg| arg == "moves").unwrap_or(args.len());
+    let mut state = match args.first() {
+        Some(&"startpos") => ChessState::new(),
+        Some(&"fen") => ChessState::from_fen(&args[1..moves_at].join(" "))?,
+        _ => return Err(()),
+    };
+    for s in args.iter().skip(moves_at + 1) {
+        let mv = notation::parse_uci(&state, s)?;
+        state.make_move(mv);
+    }
+    Ok(state)
+}
+
+const MAX_THREADS: usize = 256;
+// How deep searches with a time limit may go
+const MAX_DEPTH: u32 = 32;
+// The moves the time left on the clock is shared between, unless the GUI
+// says otherwise
+const MOVES_TO_GO: u32 = 30;
+// The milliseconds kept on the clock for passing the move on
+const MOVE_OVERHEAD: u64 = 50;
+
+// The value following the given option of a "go" command
+fn go_option(args: &[&str], name: &str) -> Option<u32> {
+    let i = args.iter().position(|&arg| arg == name)?;
+    args.get(i + 1)?.parse().ok()
+}
+
+// How long to think about a move: the movetime of a "go" command, or else a
+// share of the time left on the clock of the player to move and of their
+// increment. None without a time limit.
+fn think_time(args: &[&str], turn: Color) -> Option<Duration> {
+    if let Some(time) = go_option(args, "movetime") {
+        return Some(Duration::from_millis(time.into()));
+    }
+    let (time, increment) = match turn {
+        Color::White => ("wtime", "winc"),
+        Color::Black => ("btime", "binc"),
+    };
+    let left = u64::from(go_option(args, time)?);
+    let increment = u64::from(go_option(args, increment).unwrap_or(0));
+    let moves = u64::from(go_option(args, "movestogo").unwrap_or(MOVES_TO_GO).max(1));
+    // some time is kept for passing the move on
+    let time = (left / moves + increment * 3 / 4).min(left.saturating_sub(MOVE_OVERHEAD));
+    Some(Duration::from_millis(time))
+}
+
+fn print_info(output: &mut impl Write, result: &SearchResult) -> io::Result<()> {
+    let score = if engine::is_mate_score(result.score) {
+        // in moves, not half moves
+        let plies = engine::MATE - result.score.abs();
+        format!("mate {}", result.score.signum() * (plies + 1) / 2)
+    } else {
+        format!("cp {}", result.score)
+    };
+    let pv: Vec<String> = result.pv.iter().map(ToString::to_string).collect();
+    writeln!(output, "info depth {} score {} nodes {} pv {}", result.depth, score, result.nodes, pv.join(" "))?;
+    output.flush()
+}
+
+// Tells the search to stop and waits for it to print its move
+fn stop_search(search: &mut Option<ScopedJoinHandle<'_, io::Result<()>>>, stop: &AtomicBool) -> io::Result<()> {
+    stop.store(true, Ordering::Relaxed);
+    match search.take() {
+        Some(search) => search.join().unwrap(),
+        None => Ok(()),
+    }
+}
+
+// Speaks the Universal Chess Interface protocol, so that tress can be used as
+// an engine by other chess programs. Searches go to the given depth unless
+// the GUI asks for another one or gives a time limit, and run in a thread so
+// that "stop" ends them with the best move found so far. The number of
+// threads can be changed with the Threads option. Moves are played from the
+// opening book while the position is in it, unless the OwnBook option is
+// turned off. Positions are evaluated with the weights.
+pub fn run(input: impl BufRead,
+           output: impl Write + Send,
+           depth: u32,
+           mut threads: usize,
+           weights: Arc<Weights>,
+           book: Option<&Book>) -> io::Result<()> {
+    let shared_output = Mutex::new(output);
+    let stop = AtomicBool::new(false);
+    let mut state = ChessState::new();
+    let mut own_book = book.is_some();
+    thread::scope(|scope| {
+        let mut search = None;
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            // the search has to be done before the position or the options
+            // change, "isready" is answered while it runs
+            if let Some(&("stop" | "quit" | "go" | "position" | "ucinewgame" | "setoption")) = words.first() {
+                stop_search(&mut search, &stop)?;
+            }
+            let mut output = shared_output.lock().unwrap();
+            match words.first() {
+                Some(&"uci") => {
+                    writeln!(output, "id name tress {}", env!("CARGO_PKG_VERSION"))?;
+                    writeln!(output, "id author the tress developers")?;
+                    writeln!(output, "option name Threads type spin default {} min 1 max {}", threads, MAX_THREADS)?;
+                    if book.is_some() {
+                        writeln!(output, "option name OwnBook type check default true")?;
+                    }
+                    writeln!(output, "uciok")?;
+                },
+                Some(&"isready") => writeln!(output, "readyok")?,
+                Some(&"ucinewgame") => state = ChessState::new(),
+                Some(&"setoption") => match words.as_slice() {
+                    [_, "name", "Threads", "value", value] => match value.parse() {
+                        Ok(value) if (1..=MAX_THREADS).contains(&value) => threads = value,
+                        _ => writeln!(output, "info string invalid number of threads")?,
+                    },
+                    [_, "name", "OwnBook", "value", value] if book.is_some() => match *value {
+                        "true" => own_book = true,
+                        "false" => own_book = false,
+                        _ => writeln!(output, "info string invalid value for OwnBook")?,
+                    },
+                    _ => writeln!(output, "info string unknown option")?,
+                },
+                Some(&"position") => match parse_position(&words[1..]) {
+                    Ok(new_state) => state = new_state,
+                    Err(()) => writeln!(output, "info string invalid position")?,
+                },
+                Some(&"go") => {
+                    if let Some(mv) = book.filter(|_| own_book).and_then(|book| book.pick(&state)) {
+                        writeln!(output, "info string book move")?;
+                        writeln!(output, "bestmove {}", mv)?;
+                        output.flush()?;
+                        continue;
+                    }
+                    let time = think_time(&words, state.turn);
+                    let depth = match go_option(&words, "depth") {
+                        Some(depth) => depth,
+                        None if time.is_some() || words.contains(&"infinite") => MAX_DEPTH,
+                        None => depth,
+                    };
+                    let deadline = time.map(|time| Instant::now() + time);
+                    stop.store(false, Ordering::Relaxed);
+                    let (mut searched, weights, stop, output) = (state.clone(), weights.clone(), &stop, &shared_output);
+                    search = Some(scope.spawn(move || {
+                        let stopped = || stop.load(Ordering::Relaxed) || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+                        let result = engine::search_threads(&mut searched, depth, threads, weights, &stopped, |result| {
+                            let _ = print_info(&mut *output.lock().unwrap(), result);
+                        });
+                        // a search stopped before its first depth still plays
+                        // a legal move
+                        let best_move = result.best_move.or_else(|| searched.legal_moves().first().copied());
+                        let mut output = output.lock().unwrap();
+                        match best_move {
+                            Some(mv) => writeln!(output, "bestmove {}", mv)?,
+                            None => writeln!(output, "bestmove 0000")?,
+                        }
+                        output.flush()
+                    }));
+                },
+                Some(&"quit") => break,
+                // unknown commands are to be ignored
+                _ => (),
+            }
+            output.flush()?;
+        }
+        stop_search(&mut search, &stop)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(input: &str) -> String {
+        let mut output = vec![];
+        run(input.as_bytes(), &mut output, 3, 1, Arc::new(Weights::default()), None).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn handshake() {
+        let output = session("uci\nisready\nquit\n");
+        assert!(output.contains("uciok\n"));
+        assert!(output.ends_with("readyok\n"));
+    }
+
+    #[test]
+    fn finds_mate() {
+        let output = session("setoption name Threads value 2\n\
+                              position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 moves g1f1 g8h8 f1g1 h8g8\ngo depth 2\n");
+        assert!(output.contains("score mate 1 "));
+        assert!(output.ends_with("bestmove a1a8\n"));
+    }
+
+    #[test]
+    fn stops_searching() {
+        let started = std::time::Instant::now();
+        // stopped before it found anything, or by the end of the input
+        let output = session("position startpos\ngo infinite\nstop\nisready\ngo movetime 50\n");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(output.matches("bestmove ").count(), 2, "{}", output);
+        assert!(output.contains("readyok\n"));
+        assert!(!output.contains("bestmove 0000"));
+    }
+
+    #[test]
+    fn time_limits() {
+        let go = |command: &str, turn| think_time(&command.split_whitespace().collect::<Vec<_>>(), turn);
+        assert_eq!(go("go depth 3", Color::White), None);
+        assert_eq!(go("go movetime 250", Color::Black), Some(Duration::from_millis(250)));
+        assert_eq!(go("go wtime 60000 btime 3000 winc 1000", Color::White), Some(Duration::from_millis(2750)));
+        assert_eq!(go("go wtime 60000 btime 3000 movestogo 2", Color::Black), Some(Duration::from_millis(1500)));
+        assert_eq!(go("go wtime 20 btime 20", Color::White), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn book_moves() {
+        use crate::book::{self, Entry};
+        let entry = Entry {key: book::key(&ChessState::new()), mv: 0x31c, weight: 1, learn: 0};
+        let book = Book::parse(&entry.to_bytes()).unwrap();
+        let mut output = vec![];
+        run("uci\nposition startpos\ngo\nsetoption name OwnBook value false\ngo depth 1\n".as_bytes(),
+            &mut output, 3, 1, Arc::new(Weights::default()), Some(&book)).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("option name OwnBook type check default true\n"));
+        assert!(output.contains("info string book move\nbestmove e2e4\n"));
+        // searched without the book
+        assert!(output.contains("info depth 1 "));
+    }
+}